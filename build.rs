@@ -3,6 +3,7 @@ use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::process::Command;
 
 fn get_platform() -> Option<String> {
   let features = env::vars().filter(|&(ref key, _)| key.starts_with("CARGO_FEATURE_MCU_"));
@@ -22,12 +23,25 @@ fn file_exists(file: &Path) -> bool {
     }
 }
 
-fn copy_linker_scripts<P: AsRef<Path>, Q: AsRef<Path>>(target: P, out_path: Q) -> io::Result<()> {
-  let path_prefix = if env::var("CARGO_MANIFEST_DIR").unwrap().find("/examples/").is_none() {
+fn path_prefix() -> &'static Path {
+  if env::var("CARGO_MANIFEST_DIR").unwrap().find("/examples/").is_none() {
     Path::new(".")
   } else {
     Path::new("./../..")
-  };
+  }
+}
+
+fn run(command: &mut Command) -> io::Result<()> {
+  let status = try!(command.status());
+  if status.success() {
+    Ok(())
+  } else {
+    Err(io::Error::new(io::ErrorKind::Other, format!("{:?} failed", command)))
+  }
+}
+
+fn copy_linker_scripts<P: AsRef<Path>, Q: AsRef<Path>>(target: P, out_path: Q) -> io::Result<()> {
+  let path_prefix = path_prefix();
   // Try copying the linker scripts
   let target_dir = Path::new("src/hal").join(target);
   let out_dir: &Path = out_path.as_ref();
@@ -41,6 +55,33 @@ fn copy_linker_scripts<P: AsRef<Path>, Q: AsRef<Path>>(target: P, out_path: Q) -
   Ok(())
 }
 
+/// Assembles the context switch code of the cpu into libsched.a.
+///
+/// Cortex-M4 and Cortex-M7 use the Cortex-M3 code, which then also saves the
+/// FPU registers of tasks using them.
+fn build_scheduler<P: AsRef<Path>>(out_path: P) -> io::Result<()> {
+  let cpu = if env::var("CARGO_FEATURE_CPU_CORTEX_M0").is_ok() {
+    "cortex_m0"
+  } else {
+    "cortex_m3"
+  };
+  let fpu = env::var("CARGO_FEATURE_CPU_CORTEX_M4").is_ok() ||
+      env::var("CARGO_FEATURE_CPU_CORTEX_M7").is_ok();
+  let out_dir: &Path = out_path.as_ref();
+  let source = path_prefix().join("src/hal").join(cpu).join("sched.S");
+  let object = out_dir.join("sched.o");
+
+  let mut gcc = Command::new("arm-none-eabi-gcc");
+  if fpu {
+    gcc.arg("-DFPU_CONTEXT");
+  }
+  try!(run(gcc.arg("-c").arg(&source).arg("-o").arg(&object)));
+  try!(run(Command::new("arm-none-eabi-ar").arg("crs")
+      .arg(out_dir.join("libsched.a")).arg(&object)));
+
+  Ok(())
+}
+
 fn main() {
   let platform = match get_platform() {
     Some(p) => p,
@@ -57,6 +98,16 @@ fn main() {
     Err(e) => panic!("Failed to copy linker scripts: {}", e)
   }
 
+  // Host builds, e.g. for tests, don't link the context switch code.
+  let arm = env::var("TARGET").map(|t| t.starts_with("thumb")).unwrap_or(false);
+  if arm && env::var("CARGO_FEATURE_MULTITASKING").is_ok() {
+    match build_scheduler(&out_dir) {
+      Ok(_) => {},
+      Err(e) => panic!("Failed to build scheduler: {}", e)
+    }
+    println!("cargo:rustc-link-lib=static=sched");
+  }
+
   // Make sure that the output dir is passed to linker
   println!("cargo:rustc-link-search=native={}", out_dir);
 }
@@ -9,11 +9,7 @@ platformtree!(
     clock {
       source = "main-oscillator";
       source_frequency = 12_000_000;
-      pll {
-        m = 50;
-        n = 3;
-        divisor = 4;
-      }
+      target_frequency = 100_000_000;
     }
 
     timer {
@@ -24,7 +24,6 @@ fn main() {
       ahb_prescaler:  init::ClockAhbPrescaler::AhbDivNone,
       apb1_prescaler: init::ClockApbPrescaler::ApbDiv2,
       apb2_prescaler: init::ClockApbPrescaler::ApbDivNone,
-      flash_latency:  init::FlashLatency::FlashLatency1,
       mco:            init::McoSource::McoClockNone,
   };
   sys_clock.setup();
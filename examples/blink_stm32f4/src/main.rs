@@ -13,18 +13,12 @@ pub fn main() {
   zinc::hal::mem_init::init_stack();
   zinc::hal::mem_init::init_data();
 
-  let led1 = pin::Pin {
-    port: pin::Port::PortD,
-    pin: 13u8,
-    function: pin::Function::GPIOOut
-  };
-  let led2 = pin::Pin {
-    port: pin::Port::PortD,
-    pin: 14u8,
-    function: pin::Function::GPIOOut
-  };
-  led1.setup();
-  led2.setup();
+  let led1 = pin::Pin::new(pin::Port::PortD, 13u8,
+      pin::Mode::GpioOut(pin::OutputType::PushPull, pin::Speed::Low),
+      pin::PullType::PullNone);
+  let led2 = pin::Pin::new(pin::Port::PortD, 14u8,
+      pin::Mode::GpioOut(pin::OutputType::PushPull, pin::Speed::Low),
+      pin::PullType::PullNone);
 
   let timer = timer::Timer::new(timer::TimerPeripheral::Timer2, 16u32);
 
@@ -14,21 +14,15 @@ pub fn main() {
   zinc::hal::mem_init::init_data();
 
   // Turn off the LCD backlight (PK3)
-  let backlight = pin::Pin {
-    port: pin::Port::PortK,
-    pin: 3,
-    function: pin::Function::GPIOOut
-  };
-  backlight.setup();
+  let backlight = pin::Pin::new(pin::Port::PortK, 3,
+      pin::Mode::GpioOut(pin::OutputType::PushPull, pin::Speed::Low),
+      pin::PullType::PullNone);
   backlight.set_low();
 
   // The STM32F7 Discovery board LED is on PI1
-  let led = pin::Pin {
-    port: pin::Port::PortI,
-    pin: 1,
-    function: pin::Function::GPIOOut
-  };
-  led.setup();
+  let led = pin::Pin::new(pin::Port::PortI, 1,
+      pin::Mode::GpioOut(pin::OutputType::PushPull, pin::Speed::Low),
+      pin::PullType::PullNone);
 
   let timer = timer::Timer::new(timer::TimerPeripheral::Timer2, 16);
 
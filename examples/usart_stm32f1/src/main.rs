@@ -23,7 +23,6 @@ fn main() {
       ahb_prescaler:  init::ClockAhbPrescaler::AhbDivNone,
       apb1_prescaler: init::ClockApbPrescaler::ApbDiv2,
       apb2_prescaler: init::ClockApbPrescaler::ApbDivNone,
-      flash_latency:  init::FlashLatency::FlashLatency2,
       mco:            init::McoSource::McoClockPLL,
   };
   sys_clock.setup();
@@ -20,9 +20,11 @@ use syntax::codemap::DUMMY_SP;
 use syntax::ext::base::ExtCtxt;
 use syntax::ext::build::AstBuilder;
 use syntax::parse::token::intern;
+use syntax::print::pprust;
 use syntax::ptr::P;
 
-use builder::meta_args::{ToTyHash, set_ty_params_for_task};
+use builder::meta_args::{ToTyHash, get_ty_params_for_task,
+    set_ty_params_for_task};
 use node;
 use super::{Builder, TokenString, add_node_dependency};
 
@@ -36,18 +38,34 @@ pub fn attach(builder: &mut Builder, _: &mut ExtCtxt, node: Rc<node::Node>) {
     task_node.materializer.set(Some(build_single_task as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
     add_node_dependency(&node, &task_node);
     add_node_dependency(&task_node, &mcu_node);
+    add_args_dependencies(builder, &task_node, &task_node);
+  }
 
-    let maybe_args_node = task_node.get_by_path("args");
-    if maybe_args_node.is_some() {
-      let args_node = maybe_args_node.unwrap();
-      for (_, ref attr) in args_node.attributes.borrow().iter() {
-        match attr.value {
-          node::RefValue(ref refname) => {
-            let refnode = builder.pt.get_by_name(refname.as_str()).unwrap();
-            add_node_dependency(&task_node, &refnode);
-          },
-          _ => (),
-        }
+  let maybe_tasks_node = node.get_by_path("tasks");
+  if maybe_tasks_node.is_some() {
+    let tasks_node = maybe_tasks_node.unwrap();
+    tasks_node.materializer.set(Some(build_tasks as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
+    add_node_dependency(&node, &tasks_node);
+    add_node_dependency(&tasks_node, &mcu_node);
+    for task_node in tasks_node.subnodes().iter() {
+      add_args_dependencies(builder, &tasks_node, task_node);
+    }
+  }
+}
+
+/// Makes `node` depend on the nodes referenced from `args` of `task_node`.
+fn add_args_dependencies(builder: &Builder, node: &Rc<node::Node>,
+    task_node: &Rc<node::Node>) {
+  let maybe_args_node = task_node.get_by_path("args");
+  if maybe_args_node.is_some() {
+    let args_node = maybe_args_node.unwrap();
+    for (_, ref attr) in args_node.attributes.borrow().iter() {
+      match attr.value {
+        node::RefValue(ref refname) => {
+          let refnode = builder.pt.get_by_name(refname.as_str()).unwrap();
+          add_node_dependency(node, &refnode);
+        },
+        _ => (),
       }
     }
   }
@@ -55,10 +73,12 @@ pub fn attach(builder: &mut Builder, _: &mut ExtCtxt, node: Rc<node::Node>) {
 
 pub fn verify(_: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
   node.expect_no_attributes(cx);
-  node.expect_subnodes(cx, &["single_task"]);
-  if node.get_by_path("single_task").is_none() {
+  node.expect_subnodes(cx, &["single_task", "tasks"]);
+  let single_task = node.get_by_path("single_task").is_some();
+  let tasks = node.get_by_path("tasks").is_some();
+  if single_task == tasks {
     cx.parse_sess().span_diagnostic.span_err(node.name_span,
-        "subnode `single_task` must be present");
+        "exactly one of subnodes `single_task` and `tasks` must be present");
   }
 }
 
@@ -86,6 +106,141 @@ fn build_single_task(builder: &mut Builder, cx: &mut ExtCtxt,
   }
 }
 
+/// Builds the `tasks` node, that starts the scheduler with a task for each
+/// subnode:
+///
+///     tasks {
+///       blink {
+///         entry = "run";
+///         stack_size = 512;
+///         priority = 1;
+///         args { ... }
+///       }
+///     }
+///
+/// Task stacks are allocated in `pt`. Tasks with `args` are called with
+/// a reference to `pt::<entry>_args`, like `single_task`, and tasks without
+/// them are called with no arguments.
+fn build_tasks(builder: &mut Builder, cx: &mut ExtCtxt,
+    node: Rc<node::Node>) {
+  node.expect_no_attributes(cx);
+
+  let mut entries = HashSet::new();
+  let mut stmts = String::new();
+  let mut descriptors = vec!();
+
+  for task in node.subnodes().iter() {
+    let ok = task.expect_attributes(cx, &[
+        ("entry", node::StrAttribute),
+        ("stack_size", node::IntAttribute),
+        ("priority", node::IntAttribute)]);
+    if !task.expect_subnodes(cx, &["args"]) || !ok {
+      continue;
+    }
+
+    let name = task.path.clone();
+    let entry = task.get_string_attr("entry").unwrap();
+    let stack_size = task.get_int_attr("stack_size").unwrap();
+    let priority = task.get_int_attr("priority").unwrap();
+
+    if stack_size == 0 || stack_size % 8 != 0 {
+      cx.parse_sess().span_diagnostic.span_err(
+          task.get_attr("stack_size").value_span,
+          "stack size must be a non-zero multiple of 8");
+      continue;
+    }
+    if priority > 255 {
+      cx.parse_sess().span_diagnostic.span_err(
+          task.get_attr("priority").value_span,
+          "priority must be at most 255");
+      continue;
+    }
+    if !entries.insert(entry.clone()) {
+      cx.parse_sess().span_diagnostic.span_err(
+          task.get_attr("entry").value_span,
+          format!("entry `{}` is used by more than one task", entry).as_str());
+      continue;
+    }
+
+    let stack_name = TokenString(format!("{}_stack", name));
+    let stack_words = stack_size / 8;
+    let stack_item = quote_item!(&*cx,
+        #[link_section=".task_stacks"]
+        #[allow(non_upper_case_globals)]
+        pub static mut $stack_name: [u64; $stack_words] = [0; $stack_words];
+    ).unwrap();
+    builder.add_type_item((*stack_item).clone());
+
+    let (task_fn, arg) = match task.get_by_path("args") {
+      Some(args) => {
+        let args_expr = build_args(builder, cx, &entry, args);
+        let ty_params = get_ty_params_for_task(cx, entry.as_str());
+        let (params_decl, params_use) = if ty_params.is_empty() {
+          ("".to_string(), "".to_string())
+        } else {
+          let decl: Vec<String> = ty_params.iter().map(|ty| {
+            format!("{}: {}", ty.to_tyhash(), ty)
+          }).collect();
+          let usage: Vec<String> = ty_params.iter().map(|ty| {
+            ty.to_tyhash()
+          }).collect();
+          (format!("<{}>", decl.join(", ")),
+              format!("<{}>", usage.join(", ")))
+        };
+
+        // The scheduler passes the arguments as u32, the generic entry
+        // function is instantiated for the type of the arguments here.
+        stmts.push_str(format!("
+            let {name}_args = {args};
+            fn {name}_task{decl}(_: &pt::{entry}_args{usage})
+                -> zinc::os::task::Task {{
+              fn task{decl}(args: u32) {{
+                {entry}(unsafe {{
+                  &*(args as *const pt::{entry}_args{usage})
+                }});
+              }}
+              task{turbofish}
+            }}",
+            name=name, args=pprust::expr_to_string(&*args_expr),
+            entry=entry, decl=params_decl, usage=params_use,
+            turbofish=if params_use.is_empty() {
+              "".to_string()
+            } else {
+              format!("::{}", params_use)
+            }).as_str());
+
+        (format!("{}_task({}_args)", name, name),
+            format!("{}_args as *const _ as u32", name))
+      },
+      None => {
+        stmts.push_str(format!("
+            fn {name}_task(_: u32) {{
+              {entry}();
+            }}",
+            name=name, entry=entry).as_str());
+        (format!("{}_task", name), "0".to_string())
+      },
+    };
+
+    descriptors.push(format!(
+        "zinc::os::task::TaskDescriptor::new(\"{}\", {}, {}, &mut pt::{}_stack, {})",
+        name, task_fn, arg, name, priority));
+  }
+
+  if descriptors.is_empty() {
+    cx.parse_sess().span_diagnostic.span_err(node.name_span,
+        "at least one task must be defined");
+    return;
+  }
+
+  let block = TokenString(format!("{{
+      {}
+      let mut tasks = [{}];
+      zinc::os::task::setup(&mut tasks)
+    }}", stmts, descriptors.join(", ")));
+  builder.add_main_statement(quote_stmt!(&*cx, $block).unwrap());
+}
+
 fn build_args(builder: &mut Builder, cx: &mut ExtCtxt,
     struct_name: &String, node: Rc<node::Node>) -> P<ast::Expr> {
   let mut fields = vec!();
@@ -219,7 +374,7 @@ mod test {
   use syntax::ext::build::AstBuilder;
 
   use builder::Builder;
-  use super::build_single_task;
+  use super::{build_single_task, build_tasks};
   use test_helpers::{assert_equal_source, with_parsed};
 
   #[test]
@@ -280,4 +435,99 @@ mod test {
           }");
     });
   }
+
+  #[test]
+  fn builds_tasks() {
+    with_parsed("
+      tasks {
+        blink {
+          entry = \"run\";
+          stack_size = 512;
+          priority = 1;
+        }
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      build_tasks(&mut builder, cx, pt.get_by_path("tasks").unwrap().clone());
+      assert!(unsafe{*failed} == false);
+      assert!(builder.main_stmts.len() == 1);
+      assert!(builder.type_items.len() == 2);
+
+      assert_equal_source(&cx.stmt_item(DUMMY_SP, builder.type_items[1].clone()),
+          "#[link_section = \".task_stacks\"]
+          #[allow(non_upper_case_globals)]
+          pub static mut blink_stack: [u64; 64usize] = [0; 64usize];");
+
+      assert_equal_source(&builder.main_stmts[0],
+          "{
+            fn blink_task(_: u32) {
+              run();
+            }
+            let mut tasks = [
+              zinc::os::task::TaskDescriptor::new(
+                  \"blink\", blink_task, 0, &mut pt::blink_stack, 1)
+            ];
+            zinc::os::task::setup(&mut tasks)
+          }");
+    });
+  }
+
+  #[test]
+  fn builds_tasks_with_args() {
+    with_parsed("
+      tasks {
+        report {
+          entry = \"run\";
+          stack_size = 1024;
+          priority = 2;
+          args {
+            a = 1;
+          }
+        }
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      build_tasks(&mut builder, cx, pt.get_by_path("tasks").unwrap().clone());
+      assert!(unsafe{*failed} == false);
+      assert!(builder.main_stmts.len() == 1);
+      assert!(builder.type_items.len() == 3);
+
+      assert_equal_source(&cx.stmt_item(DUMMY_SP, builder.type_items[2].clone()),
+          "pub struct run_args<'a> {
+            pub a: u32,
+          }");
+
+      assert_equal_source(&builder.main_stmts[0],
+          "{
+            let report_args = &pt::run_args { a: 1usize, };
+            fn report_task(_: &pt::run_args) -> zinc::os::task::Task {
+              fn task(args: u32) {
+                run(unsafe { &*(args as *const pt::run_args) });
+              }
+              task
+            }
+            let mut tasks = [
+              zinc::os::task::TaskDescriptor::new(
+                  \"report\", report_task(report_args),
+                  report_args as *const _ as u32, &mut pt::report_stack, 2)
+            ];
+            zinc::os::task::setup(&mut tasks)
+          }");
+    });
+  }
+
+  #[test]
+  fn fails_to_build_tasks_with_unaligned_stack() {
+    with_parsed("
+      tasks {
+        blink {
+          entry = \"run\";
+          stack_size = 100;
+          priority = 1;
+        }
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      build_tasks(&mut builder, cx, pt.get_by_path("tasks").unwrap().clone());
+      assert!(unsafe{*failed} == true);
+      assert!(builder.main_stmts.len() == 0);
+    });
+  }
 }
@@ -17,6 +17,8 @@
 
 use core::slice::SliceExt;
 use core::convert::AsRef;
+use core::fmt;
+use core::result::Result::Ok;
 
 use core::mem::zeroed;
 
@@ -64,6 +66,16 @@ pub trait CharIO {
   }
 }
 
+/// Adapts a `CharIO` to `core::fmt::Write`, so it can be used with `write!`.
+pub struct Writer<'a>(pub &'a CharIO);
+
+impl<'a> fmt::Write for Writer<'a> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.0.puts(s);
+    Ok(())
+  }
+}
+
 #[cfg(test)]
 pub mod test {
   use core::cell::RefCell;
@@ -19,3 +19,7 @@ pub mod lcd;
 pub mod bluenrg;
 pub mod chario;
 pub mod dht22;
+pub mod semihosting;
+#[cfg(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub mod rtt;
@@ -0,0 +1,181 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+SEGGER RTT (Real Time Transfer).
+
+RTT passes data through ring buffers in RAM, which the debug probe reads and
+writes over SWD while the core runs. The probe finds the control block by its
+ID string, or through the `_SEGGER_RTT` symbol.
+
+One up (target to host) and one down (host to target) channel are provided:
+
+```ignore
+static mut UP: [u8; 512] = [0; 512];
+static mut DOWN: [u8; 16] = [0; 16];
+
+let rtt = unsafe { rtt::init(&mut UP, &mut DOWN, rtt::Mode::NoBlockSkip) };
+let _token = debug::set_backend(&rtt);
+```
+*/
+
+use core::intrinsics::{volatile_load, volatile_store};
+use core::option::Option::{self, Some, None};
+
+use drivers::chario::CharIO;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+
+/// What to do when the up buffer is full.
+#[derive(Clone, Copy)]
+pub enum Mode {
+  /// Drop the output.
+  NoBlockSkip = 0,
+  /// Wait for the host to read. Hangs if no probe is attached.
+  BlockIfFull = 2,
+}
+
+#[repr(C)]
+struct Buffer {
+  name: *const u8,
+  buffer: *mut u8,
+  size: u32,
+  /// Written by the producer.
+  write: u32,
+  /// Written by the consumer.
+  read: u32,
+  flags: u32,
+}
+
+#[repr(C)]
+struct ControlBlock {
+  id: [u8; 16],
+  max_up: u32,
+  max_down: u32,
+  up: Buffer,
+  down: Buffer,
+}
+
+#[allow(non_upper_case_globals)]
+const EmptyBuffer: Buffer = Buffer {
+  name: 0 as *const u8,
+  buffer: 0 as *mut u8,
+  size: 0,
+  write: 0,
+  read: 0,
+  flags: 0,
+};
+
+/// The control block. Its ID is only written by `init`, so the probe doesn't
+/// find it before it is set up.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut _SEGGER_RTT: ControlBlock = ControlBlock {
+  id: [0; 16],
+  max_up: 1,
+  max_down: 1,
+  up: EmptyBuffer,
+  down: EmptyBuffer,
+};
+
+#[allow(non_upper_case_globals)]
+static ChannelName: &'static str = "Terminal\0";
+
+/// Writes to the up channel, reads from the down channel.
+#[derive(Clone, Copy)]
+pub struct Rtt {
+  #[allow(dead_code)]
+  contents: ()
+}
+
+/// Sets up the control block with the given channel buffers.
+///
+/// The buffers must not be used otherwise, and `init` must only be called
+/// once.
+pub unsafe fn init(up: &'static mut [u8], down: &'static mut [u8], mode: Mode)
+    -> Rtt {
+  let cb = &mut _SEGGER_RTT;
+  cb.up = Buffer {
+    name: ChannelName.as_ptr(),
+    buffer: up.as_mut_ptr(),
+    size: up.len() as u32,
+    write: 0,
+    read: 0,
+    flags: mode as u32,
+  };
+  cb.down = Buffer {
+    name: ChannelName.as_ptr(),
+    buffer: down.as_mut_ptr(),
+    size: down.len() as u32,
+    write: 0,
+    read: 0,
+    flags: Mode::NoBlockSkip as u32,
+  };
+
+  // The probe may scan RAM for the ID, so write it last, and its first byte
+  // after the rest.
+  let id = b"SEGGER RTT";
+  for i in 1..id.len() {
+    volatile_store(&mut cb.id[i], id[i]);
+  }
+  volatile_store(&mut cb.id[0], id[0]);
+
+  Rtt { contents: () }
+}
+
+impl Rtt {
+  /// Reads a byte sent by the host, if there is one.
+  pub fn read(&self) -> Option<u8> {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      let down = &mut _SEGGER_RTT.down;
+      let read = volatile_load(&down.read);
+      if read == volatile_load(&down.write) {
+        return None;
+      }
+      let c = volatile_load(down.buffer.offset(read as isize));
+      volatile_store(&mut down.read, (read + 1) % down.size);
+      Some(c)
+    }
+  }
+}
+
+impl CharIO for Rtt {
+  fn putc(&self, value: char) {
+    loop {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        let up = &mut _SEGGER_RTT.up;
+        let write = volatile_load(&up.write);
+        let next = (write + 1) % up.size;
+        if next != volatile_load(&up.read) {
+          volatile_store(up.buffer.offset(write as isize), value as u8);
+          volatile_store(&mut up.write, next);
+          return;
+        }
+        if up.flags != Mode::BlockIfFull as u32 {
+          return;
+        }
+      }
+      // Let interrupts in while waiting for the host.
+    }
+  }
+}
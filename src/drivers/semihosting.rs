@@ -0,0 +1,85 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+ARM semihosting output.
+
+Semihosting calls stop the core on a breakpoint, and the debugger performs the
+requested operation on the host. Output shows up in the debugger console (for
+OpenOCD, after `arm semihosting enable`).
+
+Every call halts the core for a while, so this is slow. Without a debugger
+attached the breakpoint causes a HardFault.
+*/
+
+use core::slice::SliceExt;
+use core::convert::AsRef;
+
+use drivers::chario::CharIO;
+
+/// Writes a character.
+#[allow(non_upper_case_globals)]
+const SysWriteC: u32 = 0x03;
+/// Writes a NUL-terminated string.
+#[allow(non_upper_case_globals)]
+const SysWrite0: u32 = 0x04;
+
+/// Writes to the debugger console.
+#[derive(Clone, Copy)]
+pub struct Semihosting;
+
+impl CharIO for Semihosting {
+  fn putc(&self, value: char) {
+    let c = value as u8;
+    unsafe { call(SysWriteC, &c as *const u8 as u32) };
+  }
+
+  /// Outputs a string, in chunks to save on calls.
+  fn puts(&self, s: &str) {
+    let bytes: &[u8] = s.as_ref();
+    let mut chunk = [0u8; 33];
+    let mut len = 0;
+    for &c in bytes.iter() {
+      if c == 0 {
+        continue;
+      }
+      chunk[len] = c;
+      len += 1;
+      if len == chunk.len() - 1 {
+        chunk[len] = 0;
+        unsafe { call(SysWrite0, chunk.as_ptr() as u32) };
+        len = 0;
+      }
+    }
+    if len > 0 {
+      chunk[len] = 0;
+      unsafe { call(SysWrite0, chunk.as_ptr() as u32) };
+    }
+  }
+}
+
+#[cfg(target_arch = "arm")]
+unsafe fn call(op: u32, arg: u32) -> u32 {
+  let ret: u32;
+  asm!("bkpt 0xab"
+      : "={r0}"(ret)
+      : "{r0}"(op), "{r1}"(arg)
+      : "memory"
+      : "volatile");
+  ret
+}
+
+#[cfg(not(target_arch = "arm"))]
+unsafe fn call(_: u32, _: u32) -> u32 { unimplemented!() }
@@ -0,0 +1,104 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Reference-counted peripheral clock gating.
+
+A peripheral clock is enabled when the first `ClockGate` for it is created and
+disabled again once the last one is dropped. Drivers keep a gate for as long as
+they use the peripheral, so clocks of unused peripherals are turned off.
+
+A clock that is already on when its first gate is created, because it is on
+after reset or was turned on with the raw `enable` of the platform, is left
+alone: gates neither count nor disable it. Turning a gated clock off with the
+raw `disable` stops it under its users.
+*/
+
+use core::clone::Clone;
+use core::intrinsics::abort;
+use core::marker::Copy;
+use core::ops::Drop;
+
+use super::cortex_common::irq::NoInterrupts;
+
+/// A peripheral clock that can be shared through `ClockGate`.
+pub trait GatedClock: Copy {
+  /// Returns the counter of active users for this clock.
+  ///
+  /// The counter is only accessed with interrupts disabled.
+  unsafe fn users(self) -> &'static mut u8;
+
+  /// Turns the clock on or off in hardware.
+  fn set_enabled(self, enabled: bool);
+
+  /// Returns true if the clock is on in hardware.
+  fn enabled(self) -> bool;
+}
+
+/// Keeps a peripheral clock enabled while alive.
+#[must_use]
+pub struct ClockGate<C: GatedClock> {
+  clock: C,
+  /// Whether this gate is one of the counted users.
+  counted: bool,
+}
+
+impl<C: GatedClock> ClockGate<C> {
+  /// Registers a new user of the clock, enabling it if it was off.
+  pub fn new(clock: C) -> ClockGate<C> {
+    let _crit = NoInterrupts::new();
+    let users = unsafe { clock.users() };
+    if *users == 0 {
+      if clock.enabled() {
+        // Enabled outside of gates, whoever did it keeps it on.
+        return ClockGate { clock: clock, counted: false };
+      }
+      clock.set_enabled(true);
+    } else if *users == 255 {
+      unsafe { abort() };
+    }
+    *users += 1;
+
+    ClockGate {
+      clock: clock,
+      counted: true,
+    }
+  }
+
+  /// Returns the gated clock.
+  pub fn clock(&self) -> C {
+    self.clock
+  }
+}
+
+impl<C: GatedClock> Clone for ClockGate<C> {
+  fn clone(&self) -> ClockGate<C> {
+    ClockGate::new(self.clock)
+  }
+}
+
+impl<C: GatedClock> Drop for ClockGate<C> {
+  fn drop(&mut self) {
+    if !self.counted {
+      return;
+    }
+    let _crit = NoInterrupts::new();
+    let users = unsafe { self.clock.users() };
+    *users -= 1;
+    if *users == 0 {
+      self.clock.set_enabled(false);
+    }
+  }
+}
@@ -0,0 +1,217 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fault reports for Cortex-M3 and up.
+//!
+//! The default handler of NMI, HardFault, MemManage, BusFault and UsageFault
+//! (see `isr.rs`) calls `fault_handler`, which decodes the fault status
+//! registers, prints them along with the stacked registers through the output
+//! set with `set_output`, and then resets or halts.
+
+use core::option::Option::{self, Some, None};
+
+use drivers::chario::CharIO;
+use super::scb;
+
+/// What to do after a fault has been reported.
+#[derive(Clone, Copy)]
+pub enum FaultAction {
+  /// Stop, waiting for a debugger.
+  Halt,
+  /// Reset the system.
+  Reset,
+}
+
+#[allow(non_upper_case_globals)]
+static mut Output: Option<&'static CharIO> = None;
+#[allow(non_upper_case_globals)]
+static mut Action: FaultAction = FaultAction::Halt;
+
+/// Sets the output fault reports are printed to.
+pub fn set_output(output: &'static CharIO) {
+  unsafe { Output = Some(output) };
+}
+
+/// Sets what to do after a fault, the default is `FaultAction::Halt`.
+pub fn set_action(action: FaultAction) {
+  unsafe { Action = action };
+}
+
+/// Registers stacked on exception entry.
+#[repr(C)]
+#[allow(missing_docs)]
+pub struct ExceptionFrame {
+  pub r0: u32,
+  pub r1: u32,
+  pub r2: u32,
+  pub r3: u32,
+  pub r12: u32,
+  pub lr: u32,
+  pub pc: u32,
+  pub xpsr: u32,
+}
+
+/// CFSR bits.
+#[allow(non_upper_case_globals)]
+static CfsrBits: [(u32, &'static str); 19] = [
+  (1 << 0,  "IACCVIOL"),
+  (1 << 1,  "DACCVIOL"),
+  (1 << 3,  "MUNSTKERR"),
+  (1 << 4,  "MSTKERR"),
+  (1 << 5,  "MLSPERR"),
+  (1 << 7,  "MMARVALID"),
+  (1 << 8,  "IBUSERR"),
+  (1 << 9,  "PRECISERR"),
+  (1 << 10, "IMPRECISERR"),
+  (1 << 11, "UNSTKERR"),
+  (1 << 12, "STKERR"),
+  (1 << 13, "LSPERR"),
+  (1 << 15, "BFARVALID"),
+  (1 << 16, "UNDEFINSTR"),
+  (1 << 17, "INVSTATE"),
+  (1 << 18, "INVPC"),
+  (1 << 19, "NOCP"),
+  (1 << 24, "UNALIGNED"),
+  (1 << 25, "DIVBYZERO"),
+];
+
+/// HFSR bits.
+#[allow(non_upper_case_globals)]
+static HfsrBits: [(u32, &'static str); 3] = [
+  (1 << 1,  "VECTTBL"),
+  (1 << 30, "FORCED"),
+  (1 << 31, "DEBUGEVT"),
+];
+
+/// Stacking failed, so the frame doesn't hold valid registers.
+#[allow(non_upper_case_globals)]
+const StackingErrors: u32 = (1 << 4) | (1 << 12);
+
+/// Reports the fault, then resets or halts.
+///
+/// `frame` points to the registers stacked by the faulting code,
+/// `exc_return` is the EXC_RETURN value the handler was entered with.
+#[no_mangle]
+pub unsafe extern fn fault_handler(frame: *const ExceptionFrame,
+    exc_return: u32) -> ! {
+  match Output {
+    Some(out) => report(out, &*frame, exc_return),
+    None => {},
+  }
+
+  match Action {
+    FaultAction::Reset => scb::reset(),
+    FaultAction::Halt => halt(),
+  }
+}
+
+unsafe fn report(out: &CharIO, frame: &ExceptionFrame, exc_return: u32) {
+  let status = scb::fault_status();
+
+  out.puts("\n*** ");
+  out.puts(match scb::active_exception() {
+    2 => "NMI",
+    3 => "HardFault",
+    4 => "MemManage fault",
+    5 => "BusFault",
+    6 => "UsageFault",
+    _ => "unexpected exception",
+  });
+  out.puts("\n");
+
+  out.puts("HFSR  "); put_hex(out, status.hfsr);
+  put_bits(out, status.hfsr, &HfsrBits);
+  out.puts("\nCFSR  "); put_hex(out, status.cfsr);
+  put_bits(out, status.cfsr, &CfsrBits);
+  out.puts("\n");
+  match status.mmfar {
+    Some(address) => {
+      out.puts("MMFAR "); put_hex(out, address); out.puts("\n");
+    },
+    None => {},
+  }
+  match status.bfar {
+    Some(address) => {
+      out.puts("BFAR  "); put_hex(out, address); out.puts("\n");
+    },
+    None => {},
+  }
+
+  let thread = exc_return & 0xf != 1;
+  out.puts(if thread { "in thread mode" } else { "in handler mode" });
+  report_task(out, exc_return);
+  out.puts("\n");
+
+  if status.cfsr & StackingErrors != 0 {
+    out.puts("registers were not stacked\n");
+    return;
+  }
+  out.puts("R0   "); put_hex(out, frame.r0);
+  out.puts("  R1   "); put_hex(out, frame.r1);
+  out.puts("  R2   "); put_hex(out, frame.r2);
+  out.puts("  R3   "); put_hex(out, frame.r3);
+  out.puts("\nR12  "); put_hex(out, frame.r12);
+  out.puts("  LR   "); put_hex(out, frame.lr);
+  out.puts("  PC   "); put_hex(out, frame.pc);
+  out.puts("  xPSR "); put_hex(out, frame.xpsr);
+  out.puts("\n");
+}
+
+/// Prints the name of the current task, if the fault happened in one.
+#[cfg(feature = "multitasking")]
+unsafe fn report_task(out: &CharIO, exc_return: u32) {
+  use os::task::Tasks;
+
+  // Tasks run on the process stack.
+  if exc_return & 0x4 != 0 {
+    out.puts(", task ");
+    out.puts(Tasks.current_task().name);
+  }
+}
+
+#[cfg(not(feature = "multitasking"))]
+unsafe fn report_task(_: &CharIO, _: u32) {}
+
+fn put_hex(out: &CharIO, value: u32) {
+  out.puts("0x");
+  for i in 0..8 {
+    let digit = (value >> (28 - i * 4)) & 0xf;
+    out.putc(if digit < 10 {
+      ('0' as u8 + digit as u8) as char
+    } else {
+      ('a' as u8 + digit as u8 - 10) as char
+    });
+  }
+}
+
+fn put_bits(out: &CharIO, value: u32, bits: &[(u32, &'static str)]) {
+  for &(bit, name) in bits.iter() {
+    if value & bit != 0 {
+      out.puts(" ");
+      out.puts(name);
+    }
+  }
+}
+
+#[cfg(target_arch = "arm")]
+fn halt() -> ! {
+  unsafe { asm!("cpsid i" :::: "volatile") };
+  loop {
+    unsafe { asm!("wfi" :::: "volatile") };
+  }
+}
+
+#[cfg(not(target_arch = "arm"))]
+fn halt() -> ! { unimplemented!() }
@@ -46,6 +46,32 @@ impl Drop for NoInterrupts {
 #[cfg(target_os = "none")]
 static mut irq_level : usize = 0;
 
+/// Unprivileged code can neither mask interrupts nor write `irq_level`, so
+/// its critical sections are kept by the kernel: SVC 1 disables all
+/// interrupts in the NVIC, SVC 5 enables them again (see `os::syscall`).
+///
+/// Handler mode is always privileged, CONTROL.nPRIV only applies to thread
+/// mode, and still shows the state of the interrupted task. Threads only run
+/// unprivileged as isolated tasks, which are not available on Cortex-M0.
+#[cfg(all(target_os = "none", feature = "multitasking",
+          not(feature = "cpu_cortex-m0")))]
+#[inline(always)]
+pub unsafe fn unprivileged_thread() -> bool {
+  let ipsr: u32;
+  let control: u32;
+  asm!("mrs $0, ipsr" : "=r"(ipsr) ::: "volatile");
+  asm!("mrs $0, control" : "=r"(control) ::: "volatile");
+  ipsr & 0x1ff == 0 && control & 1 != 0
+}
+
+#[cfg(all(target_os = "none",
+          not(all(feature = "multitasking", not(feature = "cpu_cortex-m0")))))]
+#[inline(always)]
+pub unsafe fn unprivileged_thread() -> bool { false }
+
+#[cfg(not(target_os = "none"))]
+pub unsafe fn unprivileged_thread() -> bool { false }
+
 /// Disables all interrupts except Reset, HardFault, and NMI.
 /// Note that this is reference counted: if `disable_irqs` is called
 /// twice then interrupts will only be re-enabled upon the second call
@@ -53,6 +79,10 @@ static mut irq_level : usize = 0;
 #[cfg(target_os = "none")]
 #[inline(always)]
 unsafe fn disable_irqs() {
+  if unprivileged_thread() {
+    asm!("svc 1" :::: "volatile");
+    return;
+  }
   asm!("cpsid i" :::: "volatile");
   irq_level += 1;
 }
@@ -64,6 +94,10 @@ unsafe fn disable_irqs() { unimplemented!() }
 #[cfg(target_os = "none")]
 #[inline(always)]
 unsafe fn enable_irqs() {
+  if unprivileged_thread() {
+    asm!("svc 5" :::: "volatile");
+    return;
+  }
   if irq_level == 0 {
     abort();
   }
@@ -22,3 +22,4 @@ pub mod mpu;
 pub mod nvic;
 pub mod scb;
 pub mod irq;
+pub mod fault;
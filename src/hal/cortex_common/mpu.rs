@@ -18,13 +18,136 @@
 //! MPU memory location is 0xE000_ED90.
 //  Link: http://infocenter.arm.com/help/topic/com.arm.doc.dui0552a/BIHJJABA.html
 
-// TODO(bharrisau): Remove dead_code when MPU is implemented.
-#[allow(dead_code)]
 #[inline(always)]
 fn get_reg() -> &'static reg::MPU {
   unsafe { &*(0xE000_ED90 as *mut reg::MPU) }
 }
 
+/// Access permissions of a region.
+///
+/// Privileged code keeps read/write access to all regions, so that only
+/// unprivileged code is restricted.
+#[derive(Clone, Copy)]
+pub enum Access {
+  /// No unprivileged access.
+  PrivilegedOnly,
+  /// Unprivileged code can read.
+  ReadOnly,
+  /// Unprivileged code can read and write.
+  ReadWrite,
+}
+
+/// A memory region.
+#[derive(Clone, Copy)]
+pub struct Region {
+  /// Base address, aligned to the region size.
+  pub base: u32,
+  /// Region size is 2^(size_log2) bytes, from 32 bytes up.
+  pub size_log2: u8,
+  /// Subregions to disable, one bit for each eighth of the region.
+  pub disabled_subregions: u8,
+  /// Access permissions.
+  pub access: Access,
+  /// Whether code can be executed from the region.
+  pub executable: bool,
+  /// Whether the region holds peripherals rather than normal memory.
+  pub device: bool,
+}
+
+impl Region {
+  /// Returns the smallest region covering `start..end`.
+  ///
+  /// Subregions of the region that don't overlap the range are disabled, so
+  /// the region exceeds the range by at most an eighth of its size at each
+  /// end.
+  pub fn covering(start: u32, end: u32, access: Access, executable: bool,
+      device: bool) -> Region {
+    let mut size_log2 = 5;
+    while size_log2 < 32 {
+      let mask = !((1u32 << size_log2) - 1);
+      if (start & mask) == ((end - 1) & mask) {
+        break;
+      }
+      size_log2 += 1;
+    }
+    let base = if size_log2 == 32 { 0 } else { start & !((1u32 << size_log2) - 1) };
+
+    // Subregions are only supported for regions of 256 bytes and up.
+    let mut disabled_subregions = 0;
+    if size_log2 >= 8 {
+      let subregion = 1u32 << (size_log2 - 3);
+      for i in 0..8 {
+        let sub_start = base.wrapping_add(i * subregion);
+        let sub_end = sub_start.wrapping_add(subregion - 1);
+        if sub_end < start || sub_start >= end {
+          disabled_subregions |= 1 << i;
+        }
+      }
+    }
+
+    Region {
+      base: base,
+      size_log2: size_log2,
+      disabled_subregions: disabled_subregions,
+      access: access,
+      executable: executable,
+      device: device,
+    }
+  }
+}
+
+/// Returns the number of regions the MPU supports, 0 if there is no MPU.
+pub fn region_count() -> u8 {
+  get_reg().mpu_type.dregion() as u8
+}
+
+/// Enables the MPU.
+///
+/// Privileged code uses the default memory map outside of the regions.
+pub fn enable() {
+  get_reg().ctrl.set_privdefena(true).set_hfnmiena(false).set_enable(true);
+}
+
+/// Disables the MPU.
+pub fn disable() {
+  get_reg().ctrl.set_enable(false);
+}
+
+/// Sets up a region. Regions with higher numbers take priority where regions
+/// overlap.
+pub fn set_region(number: u8, region: &Region) {
+  use self::reg::MPU_rasr_ap as Ap;
+  let ap = match region.access {
+    Access::PrivilegedOnly => Ap::PrivilegedOnly,
+    Access::ReadOnly       => Ap::ReadOnly,
+    Access::ReadWrite      => Ap::ReadWrite,
+  };
+
+  let reg = get_reg();
+  reg.rnr.set_region(number as u32);
+  reg.rasr.set_enable(false);
+  reg.rbar.set_addr(region.base >> 5);
+  reg.rasr
+      .set_size((region.size_log2 - 1) as u32)
+      .set_srd(region.disabled_subregions as u32)
+      .set_ap(ap)
+      .set_xn(!region.executable)
+      // Normal memory is write-back cacheable and shareable, peripherals are
+      // shareable device memory.
+      .set_tex(0)
+      .set_c(!region.device)
+      .set_b(true)
+      .set_s(true)
+      .set_enable(true);
+}
+
+/// Disables a region.
+pub fn clear_region(number: u8) {
+  let reg = get_reg();
+  reg.rnr.set_region(number as u32);
+  reg.rasr.set_enable(false);
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
@@ -56,7 +179,12 @@ mod reg {
       17       => c,
       18       => s,
       19..21   => tex,
-      24..26   => ap,
+      24..26   => ap {
+        0      => NoAccess,
+        1      => PrivilegedOnly,  //= Privileged read/write only
+        2      => ReadOnly,        //= Privileged read/write, unprivileged read
+        3      => ReadWrite,       //= Full access
+      },
       28       => xn,
     }
   });
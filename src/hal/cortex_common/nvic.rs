@@ -63,6 +63,34 @@ pub fn get_priority(irqn: usize) -> u8 {
   get_reg().ipr[irqn / 4].ipr(irqn % 4) as u8
 }
 
+/// Disables all interrupts, and returns which were enabled, a bit per
+/// interrupt, to be passed to `enable_all`.
+pub fn disable_all() -> [u32; 8] {
+  let mut enabled = [0; 8];
+  for i in 0..8 {
+    enabled[i] = get_reg().iser[i].get().raw();
+    let mut icer = get_reg().icer[i].ignoring_state();
+    for bit in 0..32 {
+      if enabled[i] & (1 << bit) != 0 {
+        icer.clear_icer(bit);
+      }
+    }
+  }
+  enabled
+}
+
+/// Enables the interrupts set in `enabled`, a bit per interrupt.
+pub fn enable_all(enabled: &[u32; 8]) {
+  for i in 0..8 {
+    let mut iser = get_reg().iser[i].ignoring_state();
+    for bit in 0..32 {
+      if enabled[i] & (1 << bit) != 0 {
+        iser.clear_iser(bit);
+      }
+    }
+  }
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
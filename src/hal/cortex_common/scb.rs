@@ -19,6 +19,8 @@
 //! System Control Block ACTLR memory location is 0xE000_E008;
 //  Link: http://infocenter.arm.com/help/topic/com.arm.doc.dui0552a/CIHFDJCA.html
 
+use core::option::Option::{self, Some, None};
+
 #[inline(always)]
 fn get_reg() -> &'static reg::SCB {
   unsafe { &*(0xE000_ED00 as *mut reg::SCB) }
@@ -39,6 +41,94 @@ pub fn set_pendsv(val: bool) {
   }
 }
 
+/// Sets the priority of a system exception, 4 (MemManage) to 15 (SysTick).
+pub fn set_handler_priority(exception: usize, prio: u8) {
+  let index = exception - 4;
+  get_reg().shpr[index / 4].set_pri(index % 4, prio as u32);
+}
+
+/// Returns true if the SysTick exception is pending.
+pub fn systick_pending() -> bool {
+  get_reg().icsr.pendstset()
+}
+
+/// Makes `wfi` enter deep sleep instead of sleep.
+pub fn set_sleep_deep(deep: bool) {
+  get_reg().scr.set_sleepdeep(deep);
+}
+
+/// Fault status registers.
+#[derive(Clone, Copy)]
+pub struct FaultStatus {
+  /// Configurable fault status.
+  pub cfsr: u32,
+  /// HardFault status.
+  pub hfsr: u32,
+  /// MemManage fault address, if valid.
+  pub mmfar: Option<u32>,
+  /// BusFault address, if valid.
+  pub bfar: Option<u32>,
+}
+
+/// Reads the fault status registers.
+pub fn fault_status() -> FaultStatus {
+  let reg = get_reg();
+  let cfsr = reg.cfsr.memmanage() | (reg.cfsr.busfault() << 8) |
+      (reg.cfsr.usagefault() << 16);
+  let hfsr = ((reg.hfsr.vecttbl() as u32) << 1) |
+      ((reg.hfsr.forced() as u32) << 30) | ((reg.hfsr.debugevt() as u32) << 31);
+  FaultStatus {
+    cfsr: cfsr,
+    hfsr: hfsr,
+    // MMARVALID
+    mmfar: if cfsr & 0x80 != 0 { Some(reg.mmfar.address()) } else { None },
+    // BFARVALID
+    bfar: if cfsr & 0x8000 != 0 { Some(reg.bfar.address()) } else { None },
+  }
+}
+
+/// Returns the number of the active exception, 0 in thread mode.
+pub fn active_exception() -> u32 {
+  get_reg().icsr.vectactive()
+}
+
+/// Requests a system reset.
+pub fn reset() -> ! {
+  get_reg().aircr.set_vectkey(0x05FA).set_sysresetreq(true);
+  loop {}
+}
+
+/// Enables the MemManage fault handler.
+///
+/// While disabled, MPU violations escalate to HardFault.
+pub fn enable_memmanage_fault() {
+  get_reg().shcsr.set_memfaultena(true);
+}
+
+/// Returns the MemManage fault status and clears it.
+pub fn take_memmanage_fault() -> u8 {
+  let status = get_reg().cfsr.memmanage();
+  // Status bits are cleared by writing ones, other fault bits are left alone.
+  get_reg().cfsr.set_memmanage(status).set_busfault(0).set_usagefault(0);
+  status as u8
+}
+
+/// Returns the address that caused the last MemManage fault, if valid.
+pub fn memmanage_fault_address() -> Option<u32> {
+  // MMARVALID
+  if get_reg().cfsr.memmanage() & 0x80 != 0 {
+    Some(get_reg().mmfar.address())
+  } else {
+    None
+  }
+}
+
+/// Returns true if the active exception is the only one, so that it returns
+/// to thread mode.
+pub fn returns_to_thread() -> bool {
+  get_reg().icsr.rettobase()
+}
+
 mod reg {
   use volatile_cell::VolatileCell;
   use core::ops::Drop;
@@ -101,7 +191,7 @@ mod reg {
       13      => memfaultpended,
       14      => busfaultpended,
       15      => svfaultpended,
-      16      => memfaultpendena,
+      16      => memfaultena,
       17      => busfaultena,
       18      => usgfaultena,
     }
@@ -49,6 +49,21 @@ pub fn ten_ms() -> Option<u32> {
   }
 }
 
+/// Sets the reload value, which is used from the next time the timer wraps.
+pub fn set_reload(reload: u32) {
+  get_reg().rvr.set_reload(reload);
+}
+
+/// Returns the reload value.
+pub fn get_reload() -> u32 {
+  get_reg().rvr.reload()
+}
+
+/// Restarts the timer from the reload value and clears the count flag.
+pub fn restart() {
+  get_reg().cvr.set_current(0);
+}
+
 /// Enables the timer.
 pub fn enable() {
   get_reg().csr.set_enable(true);
@@ -24,4 +24,8 @@ pub use super::cortex_common::systick;
 pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::irq;
-pub mod lock;
+// No fault decoder, ARMv6-M has no fault status registers.
+pub use super::cortex_common::fault::ExceptionFrame;
+#[cfg(feature = "multitasking")]
+#[path="../cortex_m3/sched.rs"] pub mod sched;
+#[cfg(feature = "multitasking")] pub mod lock;
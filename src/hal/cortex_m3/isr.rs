@@ -69,11 +69,12 @@ pub unsafe extern fn isr_handler_wrapper() {
 
       .thumb_func
       isr_default_fault:
-      mrs r0, psp
-      mrs r1, msp
-      ldr r2, [r0, 0x18]
-      ldr r3, [r1, 0x18]
-      bkpt" :::: "volatile");
+      tst lr, #4
+      ite eq
+      mrseq r0, msp
+      mrsne r0, psp
+      mov r1, lr
+      b fault_handler" :::: "volatile");
 }
 
 #[allow(non_upper_case_globals)]
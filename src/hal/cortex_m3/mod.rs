@@ -25,5 +25,6 @@ pub use super::cortex_common::scb;
 pub use super::cortex_common::nvic;
 pub use super::cortex_common::mpu;
 pub use super::cortex_common::irq;
+pub use super::cortex_common::fault;
 #[cfg(feature = "multitasking")] pub mod sched;
 #[cfg(feature = "multitasking")] pub mod lock;
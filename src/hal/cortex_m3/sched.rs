@@ -14,6 +14,10 @@
 // limitations under the License.
 
 //! Cortex-M3 specific support code for scheduler.
+//!
+//! Cortex-M0, Cortex-M4 and Cortex-M7 use this module as well. The assembly
+//! part differs on Cortex-M0, and saves the FPU registers of tasks using them
+//! on Cortex-M4 and Cortex-M7.
 
 use core::ops::Drop;
 use core::intrinsics::abort;
@@ -49,6 +53,38 @@ pub fn get_task_stack_pointer() -> u32 {
 #[cfg(not(target_arch = "arm"))]
 pub fn get_task_stack_pointer() -> u32 { unimplemented!() }
 
+/// Returns true if thread mode runs privileged (CONTROL.nPRIV).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn is_privileged() -> bool {
+  let control: u32;
+  unsafe { asm!("mrs $0, control" : "=r"(control) ::: "volatile") };
+  control & 1 == 0
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub fn is_privileged() -> bool { unimplemented!() }
+
+/// Sets whether thread mode runs privileged (CONTROL.nPRIV).
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn set_privileged(privileged: bool) {
+  unsafe {
+    let mut control: u32;
+    asm!("mrs $0, control" : "=r"(control) ::: "volatile");
+    if privileged {
+      control &= !1;
+    } else {
+      control |= 1;
+    }
+    asm!("msr control, $0
+          isb" :: "r"(control) :: "volatile");
+  }
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub fn set_privileged(privileged: bool) { unimplemented!() }
+
 /// Returns current stack pointer (SP, which may be PSP or MSP).
 #[cfg(target_arch = "arm")]
 #[inline(always)]
@@ -61,6 +97,26 @@ pub fn get_current_stack_pointer() -> u32 {
 #[cfg(not(target_arch = "arm"))]
 pub fn get_current_stack_pointer() -> u32 { unimplemented!() }
 
+/// Bytes saved by sched.S below the hardware stacked state of a task: r4-r11,
+/// and EXC_RETURN on cores with an FPU context.
+#[cfg(not(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+pub const SoftwareStateSize: u32 = 8*4;
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub const SoftwareStateSize: u32 = 9*4;
+
+/// Initializes the software saved state at `stack_start`, below the hardware
+/// stacked state, so that the task starts without an FPU context.
+#[cfg(not(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+#[inline(always)]
+pub unsafe fn init_software_state(_: u32) {}
+
+#[cfg(any(feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+#[inline(always)]
+pub unsafe fn init_software_state(stack_start: u32) {
+  // EXC_RETURN: thread mode, PSP, basic frame.
+  *((stack_start + 8*4) as *mut u32) = 0xfffffffd;
+}
+
 /// State, that's saved by hardware upon entering an ISR.
 pub struct SavedState {
   pub r0: u32,
@@ -16,9 +16,10 @@
 //! HAL for NXP LPC17xx.
 
 pub mod system_clock;
+mod pll_solver;
 pub mod peripheral_clock;
 pub mod pin;
 pub mod pwm;
-// pub mod ssp;
+pub mod ssp;
 pub mod timer;
 pub mod uart;
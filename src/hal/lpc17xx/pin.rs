@@ -23,6 +23,9 @@ on the package.
 use core::intrinsics::abort;
 use core::option::Option;
 
+use hal::cortex_m3::nvic;
+use os::executor::{Future, Poll, Waker, WakerSlot};
+
 use self::Port::*;
 
 #[path="../../util/ioreg.rs"]
@@ -274,33 +277,97 @@ impl ::hal::pin::Gpio for Pin {
 impl ::hal::pin::Adc for Pin {
   /// Read analog input value of pin
   fn read(&self) -> u32 {
-    let adc = &reg::ADC;
     let channel = self.adc_channel().unwrap();
-    let mut cr = adc.CR();
-    cr &= !(0xFF as u32);
-    cr |= 1 << channel;
-    cr |= (1 << 24) as u32;
-    adc.set_CR(cr);
-
-    wait_for!((adc.STAT() & (1 << channel)) != 0);
-
-    let data = match channel {
-      0 => adc.DR0(),
-      1 => adc.DR1(),
-      2 => adc.DR2(),
-      3 => adc.DR3(),
-      4 => adc.DR4(),
-      5 => adc.DR5(),
-      6 => adc.DR6(),
-      7 => adc.DR7(),
-      _ => unsafe { abort() },
-    };
+    start_adc(channel);
+    wait_for!(adc_done(channel));
+    adc_result(channel)
+  }
+}
 
-    adc.set_CR((adc.CR() as u32) & !(1 << 24));
-    (data >> 4) & 0xFFF // 12 bit range
+impl Pin {
+  /// Returns a future that reads the analog input value of the pin.
+  ///
+  /// Only one ADC read may wait at a time. `isr_adc` must call
+  /// `on_adc_interrupt`.
+  pub fn read_adc_async<'a>(&'a self) -> AdcReadFuture<'a> {
+    AdcReadFuture { pin: self, started: false }
   }
 }
 
+/// Future returned by `Pin::read_adc_async`.
+pub struct AdcReadFuture<'a> {
+  pin: &'a Pin,
+  started: bool,
+}
+
+impl<'a> Future for AdcReadFuture<'a> {
+  type Output = u32;
+
+  fn poll(&mut self, waker: &Waker) -> Poll<u32> {
+    let channel = self.pin.adc_channel().unwrap();
+    if !self.started {
+      start_adc(channel);
+      self.started = true;
+    }
+    if !adc_done(channel) {
+      AdcWaker.register(waker);
+      reg::ADC.set_INTEN(1 << channel);
+      nvic::enable_irq(AdcIrq);
+      if !adc_done(channel) {
+        return Poll::Pending;
+      }
+    }
+    Poll::Ready(adc_result(channel))
+  }
+}
+
+/// Wakes the future waiting for an ADC conversion.
+///
+/// Must be called from `isr_adc` when using `read_adc_async`.
+pub fn on_adc_interrupt() {
+  // The future enables the interrupt again if it needs to wait.
+  reg::ADC.set_INTEN(0);
+  AdcWaker.wake();
+}
+
+#[allow(non_upper_case_globals)]
+static AdcWaker: WakerSlot = WakerSlot::new();
+
+#[allow(non_upper_case_globals)]
+const AdcIrq: usize = 22;
+
+fn start_adc(channel: u8) {
+  let adc = &reg::ADC;
+  let mut cr = adc.CR();
+  cr &= !(0xFF as u32);
+  cr |= 1 << channel;
+  cr |= (1 << 24) as u32;
+  adc.set_CR(cr);
+}
+
+fn adc_done(channel: u8) -> bool {
+  (reg::ADC.STAT() & (1 << channel)) != 0
+}
+
+/// Returns the result of the conversion started with `start_adc`.
+fn adc_result(channel: u8) -> u32 {
+  let adc = &reg::ADC;
+  let data = match channel {
+    0 => adc.DR0(),
+    1 => adc.DR1(),
+    2 => adc.DR2(),
+    3 => adc.DR3(),
+    4 => adc.DR4(),
+    5 => adc.DR5(),
+    6 => adc.DR6(),
+    7 => adc.DR7(),
+    _ => unsafe { abort() },
+  };
+
+  adc.set_CR((adc.CR() as u32) & !(1 << 24));
+  (data >> 4) & 0xFFF // 12 bit range
+}
+
 /// Sets the state of trace port interface.
 pub fn set_trace_port_interface_enabled(enabled: bool) {
   let value: u32 = if enabled { 0b1000 } else { 0 };
@@ -25,6 +25,7 @@ mod pin_pt;
 mod uart_pt;
 
 mod pinmap;
+mod pll_solver;
 
 pub fn attach(builder: &mut Builder, cx: &mut ExtCtxt, node: Rc<node::Node>) {
   node.materializer.set(Some(verify as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
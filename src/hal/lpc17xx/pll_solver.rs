@@ -0,0 +1,131 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+PLL0 parameter search.
+
+This module is shared by `system_clock` and the platformtree clock builder, so
+it must only depend on `core`.
+*/
+
+/// Lowest allowed PLL0 current controlled oscillator frequency.
+const FCCO_MIN: u32 = 275_000_000;
+/// Highest allowed PLL0 current controlled oscillator frequency.
+const FCCO_MAX: u32 = 550_000_000;
+/// Highest allowed PLL0 input frequency.
+const FIN_MAX: u32 = 25_000_000;
+/// Highest core frequency in the family (LPC1759/1769). The other parts are
+/// rated for 100MHz, and it's up to the caller to not ask for more.
+pub const CCLK_MAX: u32 = 120_000_000;
+
+/// PLL0 parameters and the resulting core frequency.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Solution {
+  /// PLL multiplier.
+  pub m: u8,
+  /// PLL divisor.
+  pub n: u8,
+  /// PLL output divisor.
+  pub divisor: u8,
+  /// Core frequency the parameters produce.
+  pub frequency: u32,
+}
+
+/// Finds PLL0 parameters that produce the highest core frequency not above
+/// `target`, given the `source` frequency.
+///
+/// Only pre-divisors that divide the source frequency evenly are considered, so
+/// the reported frequency is exact. Returns `None` if no valid configuration
+/// exists, or if `target` is above `CCLK_MAX`.
+pub fn solve(source: u32, target: u32) -> Option<Solution> {
+  let mut best: Option<Solution> = None;
+  if source == 0 || source > FIN_MAX || target == 0 || target > CCLK_MAX {
+    return None;
+  }
+
+  for n in 1..33u32 {
+    if source % n != 0 {
+      continue;
+    }
+    let reference = source / n;
+    for m in 6..256u32 {
+      let fcco = 2 * m * reference;
+      if fcco < FCCO_MIN {
+        continue;
+      }
+      if fcco > FCCO_MAX {
+        break;
+      }
+
+      // CCLKCFG values below 2 are not allowed while PLL0 is connected.
+      let mut divisor = fcco / target + if fcco % target != 0 { 1 } else { 0 };
+      if divisor < 3 {
+        divisor = 3;
+      }
+      if divisor > 255 {
+        continue;
+      }
+
+      let frequency = fcco / divisor;
+      let better = match best {
+        None => true,
+        Some(ref b) => frequency > b.frequency,
+      };
+      if better {
+        best = Some(Solution {
+          m: m as u8,
+          n: n as u8,
+          divisor: divisor as u8,
+          frequency: frequency,
+        });
+      }
+    }
+  }
+
+  best
+}
+
+#[cfg(test)]
+mod test {
+  use super::{solve, Solution};
+
+  #[test]
+  fn finds_exact_configuration() {
+    let s = solve(12_000_000, 100_000_000).unwrap();
+    assert!(s.frequency == 100_000_000);
+    assert!(2 * s.m as u32 * (12_000_000 / s.n as u32) / s.divisor as u32
+        == 100_000_000);
+  }
+
+  #[test]
+  fn rounds_down_to_closest_frequency() {
+    let s = solve(12_000_000, 99_999_999).unwrap();
+    assert!(s.frequency <= 99_999_999);
+    assert!(s.frequency > 99_000_000);
+  }
+
+  #[test]
+  fn rejects_out_of_range_input() {
+    assert!(solve(30_000_000, 100_000_000) == None::<Solution>);
+    assert!(solve(12_000_000, 0) == None::<Solution>);
+  }
+
+  #[test]
+  fn rejects_target_above_maximum_core_frequency() {
+    assert!(solve(12_000_000, 120_000_000).unwrap().frequency == 120_000_000);
+    assert!(solve(12_000_000, 120_000_001) == None::<Solution>);
+    assert!(solve(12_000_000, 200_000_000) == None::<Solution>);
+  }
+}
@@ -22,17 +22,22 @@ it's currently not supported at all.
 
 use core::intrinsics::abort;
 
-use hal::lpc17xx::peripheral_clock::{PeripheralClock, SSP0Clock, SSP1Clock};
+use hal::cortex_m3::nvic;
+use hal::lpc17xx::peripheral_clock::PeripheralClock;
+use hal::lpc17xx::peripheral_clock::PeripheralClock::SSP0Clock;
+use hal::lpc17xx::peripheral_clock::PeripheralClock::SSP1Clock;
 use hal::lpc17xx::system_clock::system_clock;
-use hal::pin::PinConf_;
 use hal::spi;
+use os::executor::{Future, Poll, Waker, WakerSlot};
+use self::SSPPeripheral::*;
 
-#[path="../../util/ioreg.rs"] mod ioreg;
+#[path="../../util/ioreg.rs"]
+#[macro_use] mod ioreg;
 
 /// SPI configuration.
 ///
-/// This configuration doesn't manage the chip-select pin, it must be configured
-/// and used externally via GPIOConf.
+/// This configuration doesn't manage the pins, MOSI, MISO and SCLK must be set
+/// up with `pin::Pin::new`, and the chip-select pin used as a GPIO.
 pub struct SPIConf {
   /// Peripheral to use, mcu-specific.
   pub peripheral: SSPPeripheral,
@@ -44,13 +49,6 @@ pub struct SPIConf {
   ///
   /// The divisor is currently hardcoded and is equal to 1.
   pub frequency: u32,
-
-  /// MOSI pin to use, supports NotConnected pin.
-  pub mosi: PinConf_,
-  /// MISO pin to use, supports NotConnected pin.
-  pub miso: PinConf_,
-  /// SCLK pin to use, supports NotConnected pin.
-  pub sclk: PinConf_,
 }
 
 impl SPIConf {
@@ -67,31 +65,36 @@ impl SPIConf {
     ssp.set_format(self.bits, self.mode);
     ssp.set_frequency(self.frequency);
 
-    self.mosi.setup();
-    self.miso.setup();
-    self.sclk.setup();
-
     ssp
   }
 }
 
 /// Opaque object that manages the configured peripheral.
-#[allow(dead_code)]
 pub struct SSP {
-  peripheral: SSPPeripheral, // TODO(farcaller): clean up the warning
+  peripheral: SSPPeripheral,
   reg: &'static reg::SSP,
 }
 
+/// Available SSP peripherals.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
 pub enum SSPPeripheral {SSP0, SSP1}
 
 impl SSPPeripheral {
-  fn reg(self) -> &reg::SSP {
+  fn reg(self) -> &'static reg::SSP {
     match self {
       SSP0 => &reg::SSP0,
       SSP1 => &reg::SSP1,
     }
   }
 
+  fn irq(self) -> usize {
+    match self {
+      SSP0 => 14,
+      SSP1 => 15,
+    }
+  }
+
   fn peripheral_clock(self) -> PeripheralClock {
     match self {
       SSP0 => SSP0Clock,
@@ -198,8 +201,96 @@ impl SSP {
 
     (val & 0b10000) == 0
   }
+
+  /// Returns a future that transfers `tx`, storing the bytes received in
+  /// `rx` as far as it is long.
+  ///
+  /// Bytes left in the receive FIFO by `write` are dropped first. Only one
+  /// transfer per SSP may wait at a time. The SSP's ISR must call
+  /// `on_interrupt`.
+  pub fn transfer_async<'a>(&'a self, tx: &'a [u8], rx: &'a mut [u8])
+      -> TransferFuture<'a> {
+    while self.readable() {
+      self.reg.DR();
+    }
+    TransferFuture { ssp: self, tx: tx, rx: rx, pos: 0, sent: false }
+  }
+}
+
+/// Future returned by `SSP::transfer_async`.
+pub struct TransferFuture<'a> {
+  ssp: &'a SSP,
+  tx: &'a [u8],
+  rx: &'a mut [u8],
+  pos: usize,
+  /// Whether `tx[pos]` was written and its answer is awaited.
+  sent: bool,
+}
+
+impl<'a> TransferFuture<'a> {
+  /// Waits for the answer to the byte sent, returns false if there is no need
+  /// to because it arrived meanwhile.
+  fn wait(&self, waker: &Waker) -> bool {
+    let reg = self.ssp.reg;
+    Wakers[self.ssp.peripheral as usize].register(waker);
+    // RX only interrupts at half full, a single byte raises the timeout.
+    reg.set_IMSC(reg.IMSC() | IMSCRTIM | IMSCRXIM);
+    nvic::enable_irq(self.ssp.peripheral.irq());
+    !self.ssp.readable()
+  }
+}
+
+impl<'a> Future for TransferFuture<'a> {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    while self.pos < self.tx.len() {
+      if !self.sent {
+        // Every byte sent is read back before the next one, so the transmit
+        // FIFO has room.
+        self.ssp.reg.set_DR(self.tx[self.pos] as u32);
+        self.sent = true;
+      }
+      if !self.ssp.readable() && self.wait(waker) {
+        return Poll::Pending;
+      }
+      let value = (self.ssp.reg.DR() & 0xff) as u8;
+      if self.pos < self.rx.len() {
+        self.rx[self.pos] = value;
+      }
+      self.pos += 1;
+      self.sent = false;
+    }
+    Poll::Ready(())
+  }
 }
 
+/// Wakes the transfer waiting on `peripheral`.
+///
+/// Must be called from the SSP's ISR (e.g. `isr_ssp_0`) when using
+/// `transfer_async`.
+pub fn on_interrupt(peripheral: SSPPeripheral) {
+  let reg = peripheral.reg();
+  // The transfer enables the interrupts again when it needs to wait.
+  reg.set_IMSC(reg.IMSC() & !(IMSCRTIM | IMSCRXIM));
+  reg.set_ICR(ICRRTIC);
+  Wakers[peripheral as usize].wake();
+}
+
+/// Wakers of transfers, indexed by `SSPPeripheral`.
+#[allow(non_upper_case_globals)]
+static Wakers: [WakerSlot; 2] = [WakerSlot::new(), WakerSlot::new()];
+
+/// Receive timeout interrupt mask.
+#[allow(non_upper_case_globals)]
+const IMSCRTIM: u32 = 0x2;
+/// Receive FIFO half full interrupt mask.
+#[allow(non_upper_case_globals)]
+const IMSCRXIM: u32 = 0x4;
+/// Receive timeout interrupt clear.
+#[allow(non_upper_case_globals)]
+const ICRRTIC: u32 = 0x2;
+
 impl spi::Spi for SSP {
   fn write(&self, value: u8) {
     loop { if self.writeable() {
@@ -224,17 +315,17 @@ impl spi::Spi for SSP {
 mod reg {
   use volatile_cell::VolatileCell;
 
-  ioreg_old!(SSP: u32, CR0, CR1, DR, SR, CPSR, IMSC, RIS, MIS, ICR, DMACR)
-  reg_rw!(SSP, u32, CR0,   set_CR0,   CR0)
-  reg_rw!(SSP, u32, CR1,   set_CR1,   CR1)
-  reg_rw!(SSP, u32, DR,    set_DR,    DR)
-  reg_r!( SSP, u32, SR,               SR)
-  reg_rw!(SSP, u32, CPSR,  set_CPSR,  CPSR)
-  reg_rw!(SSP, u32, IMSC,  set_IMSC,  IMSC)
-  reg_rw!(SSP, u32, RIS,   set_RIS,   RIS)
-  reg_rw!(SSP, u32, MIS,   set_MIS,   MIS)
-  reg_rw!(SSP, u32, ICR,   set_ICR,   ICR)
-  reg_rw!(SSP, u32, DMACR, set_DMACR, DMACR)
+  ioreg_old!(SSP: u32, CR0, CR1, DR, SR, CPSR, IMSC, RIS, MIS, ICR, DMACR);
+  reg_rw!(SSP, u32, CR0,   set_CR0,   CR0);
+  reg_rw!(SSP, u32, CR1,   set_CR1,   CR1);
+  reg_rw!(SSP, u32, DR,    set_DR,    DR);
+  reg_r!( SSP, u32, SR,               SR);
+  reg_rw!(SSP, u32, CPSR,  set_CPSR,  CPSR);
+  reg_rw!(SSP, u32, IMSC,  set_IMSC,  IMSC);
+  reg_rw!(SSP, u32, RIS,   set_RIS,   RIS);
+  reg_rw!(SSP, u32, MIS,   set_MIS,   MIS);
+  reg_rw!(SSP, u32, ICR,   set_ICR,   ICR);
+  reg_rw!(SSP, u32, DMACR, set_DMACR, DMACR);
 
   extern {
     #[link_name="lpc17xx_iomem_SSP0"] pub static SSP0: SSP;
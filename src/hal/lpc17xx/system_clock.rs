@@ -38,6 +38,18 @@ pub enum ClockSource {
   RTC,
 }
 
+impl ClockSource {
+  /// Returns the source frequency.
+  pub fn frequency(&self) -> u32 {
+    use self::ClockSource::*;
+    match *self {
+      Internal =>   4_000_000,
+      Main(freq) => freq,
+      RTC =>        32_000,
+    }
+  }
+}
+
 /// PLL0 configuration options.
 ///
 /// Frequency is calculated as
@@ -56,6 +68,29 @@ pub struct PLL0 {
   pub divisor: u8,
 }
 
+impl PLL0 {
+  /// Finds PLL0 parameters for the highest core frequency that does not exceed
+  /// `target`.
+  ///
+  /// Returns `None` if the source can't be brought to the target within PLL0
+  /// limits, or if `target` is above the 120MHz the fastest parts are rated
+  /// for.
+  pub fn for_frequency(source: ClockSource, target: u32) -> Option<PLL0> {
+    super::pll_solver::solve(source.frequency(), target).map(|s| PLL0 {
+      m: s.m,
+      n: s.n,
+      divisor: s.divisor,
+    })
+  }
+
+  /// Returns the core frequency this configuration produces from `source`.
+  pub fn frequency(&self, source: ClockSource) -> u32 {
+    // 2 * m * Fin can exceed 32 bits before the division by n.
+    (source.frequency() as u64 * self.m as u64 * 2 / self.n as u64
+        / self.divisor as u64) as u32
+  }
+}
+
 /// MCU clock configuration.
 #[derive(Clone, Copy)]
 pub struct Clock {
@@ -78,11 +113,7 @@ pub fn system_clock() -> u32 {
 #[inline(always)]
 pub fn init_clock(clock: &Clock) {
   use self::ClockSource::*;
-  let src_clock: u32 = match clock.source {
-    Internal =>   4_000_000,
-    Main(freq) => freq,
-    RTC =>        32_000,
-  };
+  let src_clock: u32 = clock.source.frequency();
   let dst_clock: u32;
 
   match clock.pll {
@@ -91,7 +122,7 @@ pub fn init_clock(clock: &Clock) {
         Main(freq) => init_main_oscillator(freq),
         _ => (),
       }
-      dst_clock = (src_clock * pll.m as u32 * 2) / pll.n as u32 / pll.divisor as u32;
+      dst_clock = pll.frequency(clock.source);
       init_flash_access(dst_clock);
       init_pll(pll, &clock.source);
     },
@@ -20,6 +20,8 @@ use syntax::ext::build::AstBuilder;
 use builder::{Builder, TokenString};
 use node;
 
+use super::pll_solver;
+
 pub fn attach(_: &mut Builder, _: &mut ExtCtxt, node: Rc<node::Node>) {
   node.materializer.set(Some(build_clock as fn(&mut Builder, &mut ExtCtxt, Rc<node::Node>)));
 }
@@ -61,23 +63,56 @@ fn build_clock(builder: &mut Builder, cx: &mut ExtCtxt,
     },
   });
 
-  let some_pll_conf = node.get_by_path("pll").and_then(|sub|
-      -> Option<(usize, usize, usize)> {
-    if !sub.expect_no_subnodes(cx) || !sub.expect_attributes(cx, &[
-        ("m", node::IntAttribute),
-        ("n", node::IntAttribute),
-        ("divisor", node::IntAttribute)]) {
-      None
-    } else {
-      let m = sub.get_int_attr("m").unwrap();
-      let n = sub.get_int_attr("n").unwrap();
-      let divisor = sub.get_int_attr("divisor").unwrap();
-      Some((m, n, divisor))
-    }
-  });
+  let some_target_frequency = node.get_int_attr("target_frequency");
+  let some_pll_conf = match (node.get_by_path("pll"), some_target_frequency) {
+    (Some(sub), None) => {
+      if !sub.expect_no_subnodes(cx) || !sub.expect_attributes(cx, &[
+          ("m", node::IntAttribute),
+          ("n", node::IntAttribute),
+          ("divisor", node::IntAttribute)]) {
+        None
+      } else {
+        let m = sub.get_int_attr("m").unwrap();
+        let n = sub.get_int_attr("n").unwrap();
+        let divisor = sub.get_int_attr("divisor").unwrap();
+        Some((m, n, divisor))
+      }
+    },
+    (None, Some(target)) => {
+      if target > pll_solver::CCLK_MAX as usize {
+        cx.span_err(node.get_attr("target_frequency").value_span,
+            format!("target frequency is above the maximum of {}",
+                pll_solver::CCLK_MAX).as_str());
+        return;
+      }
+      match pll_solver::solve(source_freq as u32, target as u32) {
+        Some(s) => {
+          if s.frequency as usize != target {
+            cx.span_warn(node.get_attr("target_frequency").value_span,
+                format!("target frequency is not reachable, using {}",
+                    s.frequency).as_str());
+          }
+          Some((s.m as usize, s.n as usize, s.divisor as usize))
+        },
+        None => {
+          cx.span_err(node.get_attr("target_frequency").value_span,
+              "no valid pll configuration for target frequency");
+          return;
+        },
+      }
+    },
+    (Some(sub), Some(_)) => {
+      cx.span_err(sub.name_span,
+          "`pll` subnode conflicts with `target_frequency`");
+      return;
+    },
+    (None, None) => {
+      cx.parse_sess().span_diagnostic.span_err(node.name_span,
+          "either a `pll` subnode or `target_frequency` is required");
+      return;
+    },
+  };
   if some_pll_conf.is_none() {
-    cx.parse_sess().span_diagnostic.span_err(node.name_span,
-        "required subnode `pll` is missing");
     return;
   }
 
@@ -170,6 +205,58 @@ mod test {
     });
   }
 
+  #[test]
+  fn builds_clock_init_for_target_frequency() {
+    with_parsed("
+      clock {
+        source = \"main-oscillator\";
+        source_frequency = 12_000_000;
+        target_frequency = 100_000_000;
+      }", |cx, failed, pt| {
+      let mut builder = Builder::new(pt.clone(), cx);
+      let node = pt.get_by_path("clock").unwrap();
+      super::build_clock(&mut builder, cx, node.clone());
+      assert!(unsafe{*failed} == false);
+      assert!(builder.main_stmts().len() == 1);
+
+      let out_freq = node.get_int_attr("system_frequency");
+      assert!(out_freq.is_some());
+      assert!(out_freq.unwrap() == 100_000_000);
+    });
+  }
+
+  #[test]
+  fn fails_to_parse_unreachable_target_frequency() {
+    fails_to_build("lpc17xx@mcu { clock {
+      source = \"main-oscillator\";
+      source_frequency = 30_000_000;
+      target_frequency = 100_000_000;
+    }}");
+  }
+
+  #[test]
+  fn fails_to_parse_too_high_target_frequency() {
+    fails_to_build("lpc17xx@mcu { clock {
+      source = \"main-oscillator\";
+      source_frequency = 12_000_000;
+      target_frequency = 150_000_000;
+    }}");
+  }
+
+  #[test]
+  fn fails_to_parse_pll_and_target_frequency() {
+    fails_to_build("lpc17xx@mcu { clock {
+      source = \"main-oscillator\";
+      source_frequency = 12_000_000;
+      target_frequency = 100_000_000;
+      pll {
+        m = 50;
+        n = 3;
+        divisor = 4;
+      }
+    }}");
+  }
+
   #[test]
   fn fails_to_parse_bad_clock_conf() {
     fails_to_build("lpc17xx@mcu { clock {
@@ -17,9 +17,15 @@
 Timer configuration.
 
 This code supports all four primary timers of the MCU.
+
+Match register 0 of a timer is reserved for `Timer::delay_us_async`, which
+overwrites it along with its interrupt bit in MCR. Timers used for delays must
+not use MR0 otherwise, including to reset or stop the counter on a match.
 */
 
+use hal::cortex_m3::nvic;
 use hal::timer;
+use os::executor::{Future, Poll, Waker, WakerSlot};
 
 use self::TimerPeripheral::*;
 
@@ -50,6 +56,7 @@ pub struct TimerConf {
 /// Struct describing a timer instance.
 #[derive(Clone, Copy)]
 pub struct Timer {
+  peripheral: TimerPeripheral,
   reg: &'static reg::TIMER,
 }
 
@@ -57,12 +64,13 @@ impl Timer {
   /// Create an start a timer.
   pub fn new(peripheral: TimerPeripheral, counter: u32, divisor: u8) -> Timer {
     use hal::lpc17xx::peripheral_clock::PeripheralClock as Clock;
-    let (clock, reg) = match peripheral {
-      Timer0 => (Clock::TIM0Clock, &reg::TIMER0),
-      Timer1 => (Clock::TIM1Clock, &reg::TIMER1),
-      Timer2 => (Clock::TIM2Clock, &reg::TIMER2),
-      Timer3 => (Clock::TIM3Clock, &reg::TIMER3),
+    let clock = match peripheral {
+      Timer0 => Clock::TIM0Clock,
+      Timer1 => Clock::TIM1Clock,
+      Timer2 => Clock::TIM2Clock,
+      Timer3 => Clock::TIM3Clock,
     };
+    let reg = peripheral.reg();
 
     clock.enable();
     clock.set_divisor(divisor);
@@ -73,11 +81,94 @@ impl Timer {
     reg.set_TCR(1);
 
     Timer {
+      peripheral: peripheral,
       reg: reg,
     }
   }
+
+  /// Returns a future that completes after `us` counter ticks (microseconds
+  /// with the usual configuration).
+  ///
+  /// Uses match register 0, which is reserved for delays, so only one delay
+  /// per timer may wait at a time. The timer's ISR must call `on_interrupt`.
+  pub fn delay_us_async(&self, us: u32) -> DelayFuture {
+    DelayFuture {
+      timer: *self,
+      start: timer::Timer::get_counter(self),
+      ticks: us,
+    }
+  }
+}
+
+impl TimerPeripheral {
+  fn reg(self) -> &'static reg::TIMER {
+    match self {
+      Timer0 => &reg::TIMER0,
+      Timer1 => &reg::TIMER1,
+      Timer2 => &reg::TIMER2,
+      Timer3 => &reg::TIMER3,
+    }
+  }
+
+  fn irq(self) -> usize {
+    1 + self as usize
+  }
+}
+
+/// Future returned by `Timer::delay_us_async`.
+pub struct DelayFuture {
+  timer: Timer,
+  start: u32,
+  ticks: u32,
+}
+
+impl DelayFuture {
+  fn elapsed(&self) -> bool {
+    self.timer.reg.TC().wrapping_sub(self.start) >= self.ticks
+  }
+}
+
+impl Future for DelayFuture {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    if !self.elapsed() {
+      let reg = self.timer.reg;
+      Wakers[self.timer.peripheral as usize].register(waker);
+      reg.set_MR0(self.start.wrapping_add(self.ticks));
+      reg.set_MCR(reg.MCR() | MCRMR0I);
+      nvic::enable_irq(self.timer.peripheral.irq());
+      if !self.elapsed() {
+        return Poll::Pending;
+      }
+    }
+    Poll::Ready(())
+  }
 }
 
+/// Wakes the delay waiting on `peripheral`.
+///
+/// Must be called from the timer's ISR (e.g. `isr_timer_0`) when using
+/// `delay_us_async`.
+pub fn on_interrupt(peripheral: TimerPeripheral) {
+  let reg = peripheral.reg();
+  reg.set_MCR(reg.MCR() & !MCRMR0I);
+  reg.set_IR(IRMR0);
+  Wakers[peripheral as usize].wake();
+}
+
+/// Wakers of delays, indexed by `TimerPeripheral`.
+#[allow(non_upper_case_globals)]
+static Wakers: [WakerSlot; 4] =
+    [WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
+/// Interrupt on MR0 match.
+#[allow(non_upper_case_globals)]
+const MCRMR0I: u32 = 0x1;
+/// MR0 interrupt flag.
+#[allow(non_upper_case_globals)]
+const IRMR0: u32 = 0x1;
+
 impl timer::Timer for Timer {
   #[inline(always)]
   fn get_counter(&self) -> u32 {
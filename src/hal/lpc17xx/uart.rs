@@ -20,14 +20,17 @@ This code doesn't support UART1, while it really should (UART1 has more features
 than other UARTs in MCU).
 */
 
+use core::cmp::min;
 use core::intrinsics::abort;
 
+use hal::cortex_m3::nvic;
 use hal::lpc17xx::peripheral_clock::PeripheralClock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART0Clock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART2Clock;
 use hal::lpc17xx::peripheral_clock::PeripheralClock::UART3Clock;
 use drivers::chario::CharIO;
 use hal::uart;
+use os::executor::{Future, Poll, Waker, WakerSlot};
 
 use self::UARTPeripheral::*;
 
@@ -131,6 +134,7 @@ enum FIFOTriggerLevel {
 /// Structure describing a UART instance.
 #[derive(Clone)]
 pub struct UART {
+  peripheral: UARTPeripheral,
   reg: &'static reg::UART,
   clock: PeripheralClock,
 }
@@ -151,6 +155,14 @@ impl UARTPeripheral {
       UART3 => UART3Clock,
     }
   }
+
+  fn irq(self) -> usize {
+    match self {
+      UART0 => 5,
+      UART2 => 7,
+      UART3 => 8,
+    }
+  }
 }
 
 impl UART {
@@ -158,6 +170,7 @@ impl UART {
   pub fn new(peripheral: UARTPeripheral, baudrate: u32, word_len: u8,
       parity: uart::Parity, stop_bits: u8) -> UART {
     let uart = UART {
+      peripheral: peripheral,
       reg: peripheral.reg(),
       clock: peripheral.peripheral_clock(),
     };
@@ -282,6 +295,123 @@ impl UART {
   }
 }
 
+impl UART {
+  /// Returns a future that writes `data`, completing when all of it is in
+  /// the transmit FIFO.
+  ///
+  /// Only one write future per UART may wait at a time. The UART's ISR must
+  /// call `on_interrupt`.
+  pub fn write_async<'a>(&'a self, data: &'a [u8]) -> WriteFuture<'a> {
+    WriteFuture { uart: self, data: data, pos: 0 }
+  }
+
+  /// Returns a future that reads a byte.
+  ///
+  /// Only one read future per UART may wait at a time. The UART's ISR must
+  /// call `on_interrupt`.
+  pub fn read_async<'a>(&'a self) -> ReadFuture<'a> {
+    ReadFuture { uart: self }
+  }
+
+  /// Enables the interrupt for `source` and waits for it with `waker`.
+  ///
+  /// Returns false if the `ready` status bit got set meanwhile, so there is
+  /// no need to wait.
+  fn wait_for_irq(&self, slot: &WakerSlot, source: u32, waker: &Waker,
+      ready: u8) -> bool {
+    slot.register(waker);
+    self.reg.set_IER(self.reg.IER() | source);
+    nvic::enable_irq(self.peripheral.irq());
+    self.reg.LSR() as u8 & ready == 0
+  }
+}
+
+/// Future returned by `UART::write_async`.
+pub struct WriteFuture<'a> {
+  uart: &'a UART,
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Future for WriteFuture<'a> {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    while self.pos < self.data.len() {
+      if self.uart.reg.LSR() as u8 & LSRTHREmpty == 0 {
+        let slot = &TxWakers[self.uart.peripheral as usize];
+        if self.uart.wait_for_irq(slot, IERTHRE, waker, LSRTHREmpty) {
+          return Poll::Pending;
+        }
+      }
+      // THR empty means the whole FIFO is.
+      let end = min(self.pos + FIFOSize, self.data.len());
+      for &c in self.data[self.pos..end].iter() {
+        self.uart.reg.set_THR(c as u32);
+      }
+      self.pos = end;
+    }
+    Poll::Ready(())
+  }
+}
+
+/// Future returned by `UART::read_async`.
+pub struct ReadFuture<'a> {
+  uart: &'a UART,
+}
+
+impl<'a> Future for ReadFuture<'a> {
+  type Output = u8;
+
+  fn poll(&mut self, waker: &Waker) -> Poll<u8> {
+    if self.uart.reg.LSR() as u8 & LSRRDReady == 0 {
+      let slot = &RxWakers[self.uart.peripheral as usize];
+      if self.uart.wait_for_irq(slot, IERRBR, waker, LSRRDReady) {
+        return Poll::Pending;
+      }
+    }
+    Poll::Ready(self.uart.reg.RBR() as u8)
+  }
+}
+
+/// Wakes the futures waiting for the interrupts pending on `peripheral`.
+///
+/// Must be called from the UART's ISR (e.g. `isr_uart_0`) when using
+/// `write_async` or `read_async`.
+pub fn on_interrupt(peripheral: UARTPeripheral) {
+  let reg = peripheral.reg();
+  // IIR reports the pending source with the highest priority, and reading it
+  // clears a THRE interrupt. Futures enable their interrupt again when they
+  // need to wait.
+  loop {
+    let iir = reg.IIR();
+    if iir & IIRNonePending != 0 {
+      break;
+    }
+    match (iir >> 1) & 0b111 {
+      IIRIdTHRE => {
+        reg.set_IER(reg.IER() & !IERTHRE);
+        TxWakers[peripheral as usize].wake();
+      },
+      IIRIdRDA | IIRIdCTI => {
+        reg.set_IER(reg.IER() & !IERRBR);
+        RxWakers[peripheral as usize].wake();
+      },
+      // Line status interrupts are never enabled.
+      _ => break,
+    }
+  }
+}
+
+/// Wakers of write futures, indexed by `UARTPeripheral`.
+#[allow(non_upper_case_globals)]
+static TxWakers: [WakerSlot; 3] =
+    [WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+/// Wakers of read futures, indexed by `UARTPeripheral`.
+#[allow(non_upper_case_globals)]
+static RxWakers: [WakerSlot; 3] =
+    [WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
 impl CharIO for UART {
   fn putc(&self, value: char) {
     wait_for!(self.reg.LSR() as u8 & LSRTHREmpty == LSRTHREmpty);
@@ -299,6 +429,25 @@ static LCRModeMask: u8 = 0b1_11_1_1_11;
 
 #[allow(non_upper_case_globals)]
 static LSRTHREmpty: u8 = 0x20;
+#[allow(non_upper_case_globals)]
+static LSRRDReady: u8 = 0x01;
+
+#[allow(non_upper_case_globals)]
+const IERRBR: u32 = 0x01;
+#[allow(non_upper_case_globals)]
+const IERTHRE: u32 = 0x02;
+
+#[allow(non_upper_case_globals)]
+const IIRNonePending: u32 = 0x01;
+#[allow(non_upper_case_globals)]
+const IIRIdTHRE: u32 = 0b001;
+#[allow(non_upper_case_globals)]
+const IIRIdRDA: u32 = 0b010;
+#[allow(non_upper_case_globals)]
+const IIRIdCTI: u32 = 0b110;
+
+#[allow(non_upper_case_globals)]
+const FIFOSize: usize = 16;
 
 mod reg {
   use volatile_cell::VolatileCell;
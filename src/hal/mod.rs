@@ -35,6 +35,8 @@ pub mod lpc11xx;
           feature = "cpu_cortex-m4",
           feature = "cpu_cortex-m7"))]
 mod cortex_common;
+#[cfg(feature = "cpu_cortex-m0")]
+pub mod cortex_m0;
 #[cfg(feature = "cpu_cortex-m3")]
 pub mod cortex_m3;
 #[cfg(feature = "cpu_cortex-m4")]
@@ -42,6 +44,11 @@ pub mod cortex_m4;
 #[cfg(feature = "cpu_cortex-m7")]
 pub mod cortex_m7;
 
+#[cfg(any(feature = "mcu_stm32f1",
+          feature = "mcu_stm32f4",
+          feature = "mcu_stm32f7",
+          feature = "mcu_stm32l1"))]
+pub mod clock_gate;
 pub mod mem_init;
 pub mod pin;
 pub mod pwm;
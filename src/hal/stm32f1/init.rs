@@ -167,13 +167,43 @@ pub enum ClockApbPrescaler {
     ApbDiv16,
 }
 
+/// Flash wait states, derived from the system clock frequency.
 #[allow(missing_docs)]
 #[repr(u8)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub enum FlashLatency {
-    FlashLatency0,
-    FlashLatency1,
-    FlashLatency2,
+    /*  0 < SYSCLK <= 24 MHz */ FlashLatency0,
+    /* 24 < SYSCLK <= 48 MHz */ FlashLatency1,
+    /* 48 < SYSCLK <= 72 MHz */ FlashLatency2,
+}
+
+impl FlashLatency {
+  /// Returns the number of wait states required for given system clock.
+  pub fn for_frequency(frequency: u32) -> FlashLatency {
+    match frequency {
+      0...24_000_000 => FlashLatency0,
+      24_000_001...48_000_000 => FlashLatency1,
+      _ => FlashLatency2,
+    }
+  }
+
+  /// Returns the number of wait states flash is currently configured with.
+  fn current() -> FlashLatency {
+    match reg::FLASH.acr.latency() {
+      0 => FlashLatency0,
+      1 => FlashLatency1,
+      _ => FlashLatency2,
+    }
+  }
+
+  /// Programs flash with this number of wait states.
+  fn apply(self) {
+    let flash = &reg::FLASH;
+    flash.acr.set_prefetch_buf_on(true);
+    wait_for!(flash.acr.prefetch_buf_status());
+    flash.acr.set_latency(self as u32);
+    wait_for!(flash.acr.latency() == self as u32);
+  }
 }
 
 /// System clock configuration.
@@ -184,7 +214,6 @@ pub struct ClockConfig {
   pub ahb_prescaler : ClockAhbPrescaler,
   pub apb1_prescaler : ClockApbPrescaler,
   pub apb2_prescaler : ClockApbPrescaler,
-  pub flash_latency : FlashLatency,
   pub mco : McoSource,
 }
 
@@ -195,7 +224,6 @@ impl default::Default for ClockConfig {
       ahb_prescaler : AhbDivNone,
       apb1_prescaler : ApbDivNone,
       apb2_prescaler : ApbDivNone,
-      flash_latency : FlashLatency0,
       mco: McoClockNone,
     }
   }
@@ -208,9 +236,18 @@ impl ClockConfig {
   }
 
   /// Set this configuration on the hardware.
+  ///
+  /// Flash wait states are derived from the system clock frequency.
   pub fn setup(&self) {
     let rcc = &reg::RCC;
-    let flash = &reg::FLASH;
+
+    // Raising the clock requires the extra wait states to be in place before
+    // the switch, removing them is only safe after it.
+    let flash_latency = FlashLatency::for_frequency(self.source.frequency());
+    let previous_latency = FlashLatency::current();
+    if flash_latency > previous_latency {
+      flash_latency.apply();
+    }
 
     let source_type = match self.source {
       SystemClockHSI => {
@@ -280,17 +317,13 @@ impl ClockConfig {
        }
     };
 
-    /* TODO(blazewicz): configuring flash latency is straightforward
-     * and could be done automatically:
-     *       0 < SYSCLK <= 24 MHz => 0
-     *  24 MHz < SYSCLK <= 48 MHz => 1
-     *  48 MHz < SYSCLK <= 72 MHz => 2
-     */
-    flash.acr.set_latency(self.flash_latency as u32);
-
     rcc.cfgr.set_system_clock(source_type);
     wait_for!(rcc.cfgr.system_clock_status() == source_type);
 
+    if flash_latency < previous_latency {
+      flash_latency.apply();
+    }
+
     let ahb_select = match self.ahb_prescaler {
         AhbDivNone => 0b0000u32,
         AhbDiv2    => 0b1000u32,
@@ -21,6 +21,7 @@
 
 use super::init::{ClockConfig, reg};
 use core::marker::Copy;
+use hal::clock_gate::{ClockGate, GatedClock};
 
 pub use self::PeripheralClock::*;
 
@@ -62,6 +63,10 @@ impl BusAhb {
     let val = reg::RCC.ahbenr.enable() & mask;
     reg::RCC.ahbenr.set_enable(val | bit);
   }
+
+  fn reg_enabled(self) -> bool {
+    reg::RCC.ahbenr.enable() & self.to_reg_bit() != 0
+  }
 }
 
 #[allow(missing_docs)]
@@ -130,6 +135,10 @@ impl BusApb1 {
     let val = reg::RCC.apb1enr.enable() & mask;
     reg::RCC.apb1enr.set_enable(val | bit);
   }
+
+  fn reg_enabled(self) -> bool {
+    reg::RCC.apb1enr.enable() & self.to_reg_bit() != 0
+  }
 }
 
 impl Copy for BusApb1 {}
@@ -190,6 +199,10 @@ impl BusApb2 {
     let val = reg::RCC.apb2enr.enable() & mask;
     reg::RCC.apb2enr.set_enable(val | bit);
   }
+
+  fn reg_enabled(self) -> bool {
+    reg::RCC.apb2enr.enable() & self.to_reg_bit() != 0
+  }
 }
 
 impl Copy for BusApb2 {}
@@ -207,7 +220,26 @@ pub enum PeripheralClock {
 
 impl Copy for PeripheralClock {}
 
+/// Number of users of each clock, indexed by `PeripheralClock::index`.
+static mut CLOCK_USERS: [u8; 96] = [0; 96];
+
 impl PeripheralClock {
+  /// Returns a gate that keeps the clock enabled while it is alive.
+  ///
+  /// Prefer this over `enable` for clocks that might be shared.
+  pub fn gate(self) -> ClockGate<PeripheralClock> {
+    ClockGate::new(self)
+  }
+
+  /// Returns the position of this clock in `CLOCK_USERS`.
+  fn index(self) -> usize {
+    match self {
+      Ahb(ahb)  => ahb as usize,
+      Apb1(apb) => 32 + apb as usize,
+      Apb2(apb) => 64 + apb as usize,
+    }
+  }
+
   /// Enables the given peripheral clock.
   pub fn enable(self) {
     self.set_enable(true);
@@ -227,6 +259,15 @@ impl PeripheralClock {
     }
   }
 
+  /// Returns true if the clock is enabled.
+  fn is_enabled(self) -> bool {
+    match self {
+        Ahb(ahb)  => ahb.reg_enabled(),
+        Apb1(apb) => apb.reg_enabled(),
+        Apb2(apb) => apb.reg_enabled(),
+    }
+  }
+
   /// Returns the clock freqency
   pub fn frequency(self, cc: &ClockConfig) -> u32 {
     match self {
@@ -236,3 +277,17 @@ impl PeripheralClock {
     }
   }
 }
+
+impl GatedClock for PeripheralClock {
+  unsafe fn users(self) -> &'static mut u8 {
+    &mut CLOCK_USERS[self.index()]
+  }
+
+  fn set_enabled(self, enabled: bool) {
+    self.set_enable(enabled);
+  }
+
+  fn enabled(self) -> bool {
+    self.is_enabled()
+  }
+}
@@ -19,6 +19,7 @@
 //! depending on the package.
 
 use super::peripheral_clock;
+use hal::clock_gate::ClockGate;
 use core::intrinsics::abort;
 use self::Port::*;
 
@@ -63,12 +64,19 @@ pub enum PinConf {
 }
 
 /// Pin configuration.
-#[derive(Clone, Copy)]
+///
+/// The port clock stays enabled while any pin of the port is alive. A pin is
+/// `Clone` but not `Copy`, a clone keeps the port clock enabled like the
+/// original.
+#[derive(Clone)]
 pub struct Pin {
   /// Pin index.
   pub index: u8,
   /// GPIO register
   reg: &'static reg::GPIO,
+  /// Port clock
+  #[allow(dead_code)]
+  clock: ClockGate<peripheral_clock::PeripheralClock>,
 }
 
 impl Pin {
@@ -86,8 +94,7 @@ impl Pin {
       PortF => (&reg::GPIOF, clock::GpioF),
       PortG => (&reg::GPIOG, clock::GpioG),
     };
-    // TODO(farcaller): should be done once per port
-    peripheral_clock::PeripheralClock::Apb2(clock).enable();
+    let clock = peripheral_clock::PeripheralClock::Apb2(clock).gate();
 
     let conf: u32 = match mode {
       /* Input mode */
@@ -125,6 +132,7 @@ impl Pin {
     Pin {
       index: pin_index,
       reg: reg,
+      clock: clock,
     }
   }
 }
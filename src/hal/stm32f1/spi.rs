@@ -19,6 +19,9 @@ use core::result::Result;
 use core::result::Result::{Ok, Err};
 use core::marker::Copy;
 
+use hal::clock_gate::ClockGate;
+use hal::stm32f1::peripheral_clock::PeripheralClock;
+
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
 
@@ -101,9 +104,14 @@ pub enum Error {
 }
 
 /// Structure describing a SPI instance.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Spi {
   reg: &'static reg::SPI,
+  #[allow(dead_code)]
+  clock: ClockGate<PeripheralClock>,
 }
 
 impl Spi {
@@ -119,7 +127,7 @@ impl Spi {
       Peripheral::Spi3 => (&reg::SPI3, clock::Apb1(clock::BusApb1::Spi3)),
     };
 
-    clock.enable();
+    let gate = clock.gate();
 
     // set direction
     reg.cr1.set_receive_only(direction == Direction::RxOnly);
@@ -157,6 +165,7 @@ impl Spi {
       reg.cr1.set_spi_enable(true);
       Ok(Spi {
         reg: reg,
+        clock: gate,
       })
     }
   }
@@ -17,6 +17,9 @@
 //!
 //! This code supports only TIM2 at the moment.
 
+use hal::clock_gate::ClockGate;
+use hal::stm32f1::peripheral_clock::PeripheralClock;
+
 #[path="../../util/ioreg.rs"] mod ioreg;
 
 /// Available timer peripherals.
@@ -27,9 +30,14 @@ pub enum TimerPeripheral {
 }
 
 /// Structure describing a Timer.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Timer {
   reg: &'static reg::TIMER,
+  #[allow(dead_code)]
+  clock: ClockGate<PeripheralClock>,
 }
 
 impl Timer {
@@ -41,7 +49,7 @@ impl Timer {
       Timer2 => (&reg::TIM2, pc::BusApb1::Tim2),
     };
 
-    pc::PeripheralClock::Apb1(clock).enable();
+    let clock = pc::PeripheralClock::Apb1(clock).gate();
 
     reg.cr1.set_counter_enable(true);
     reg.cr1.set_divisor_shift(div_shift);
@@ -50,6 +58,7 @@ impl Timer {
 
     Timer {
       reg: reg,
+      clock: clock,
     }
   }
 }
@@ -20,6 +20,9 @@
 
 use hal::mem_init::init_data;
 use core::intrinsics::abort;
+use core::option::Option;
+
+use super::pll_solver;
 
 #[path="../../util/ioreg.rs"]
 #[macro_use] mod ioreg;
@@ -69,11 +72,91 @@ pub struct PLLConf {
   pub q: u8,
 }
 
+/// STM32F4 product line, lines differ in the highest system clock and the
+/// lowest PLL VCO frequency.
+#[derive(Clone, Copy)]
+pub enum ProductLine {
+  /// STM32F401, up to 84MHz.
+  ProductLineF401,
+  /// STM32F405, F407, F415 and F417, up to 168MHz.
+  ProductLineF405,
+  /// STM32F410, F411, F412, F413 and F423, up to 100MHz.
+  ProductLineF411,
+  /// STM32F427, F429, F437, F439, F446, F469 and F479, up to 180MHz. Above
+  /// 168MHz, the over-drive mode must be enabled.
+  ProductLineF427,
+}
+
+impl ProductLine {
+  /// Returns the highest system clock frequency of the line.
+  pub fn max_frequency(self) -> u32 {
+    self.limits().sysclk_max
+  }
+
+  fn limits(self) -> pll_solver::Limits {
+    use self::ProductLine::*;
+    let (vco_min, sysclk_max) = match self {
+      ProductLineF401 => (192_000_000, 84_000_000),
+      ProductLineF405 => (100_000_000, 168_000_000),
+      ProductLineF411 => (100_000_000, 100_000_000),
+      ProductLineF427 => (100_000_000, 180_000_000),
+    };
+    pll_solver::Limits { vco_min: vco_min, sysclk_max: sysclk_max }
+  }
+}
+
+/// MCU supply voltage range.
+///
+/// Flash access time depends on the supply voltage, so it determines the number
+/// of wait states required for a given clock.
+#[derive(Clone, Copy)]
+pub enum SupplyVoltage {
+  /// 1.8V to 2.1V, prefetch is not available.
+  SupplyVoltage1V8,
+  /// 2.1V to 2.4V.
+  SupplyVoltage2V1,
+  /// 2.4V to 2.7V.
+  SupplyVoltage2V4,
+  /// 2.7V to 3.6V.
+  SupplyVoltage2V7,
+}
+
+impl SupplyVoltage {
+  /// Returns the number of flash wait states required for given HCLK.
+  pub fn flash_latency(self, freq: u32) -> u32 {
+    use self::SupplyVoltage::*;
+    let step = match self {
+      SupplyVoltage1V8 => 20_000_000,
+      SupplyVoltage2V1 => 22_000_000,
+      SupplyVoltage2V4 => 24_000_000,
+      SupplyVoltage2V7 => 30_000_000,
+    };
+    let latency = if freq == 0 { 0 } else { (freq - 1) / step };
+    // STM32F42x and up have a 4 bit LATENCY field.
+    if freq > 180_000_000 || latency > 15 {
+      unsafe { abort() };
+    }
+    latency
+  }
+
+  /// Returns true if flash prefetch can be used in this range.
+  fn prefetch_available(self) -> bool {
+    match self {
+      SupplyVoltage::SupplyVoltage1V8 => false,
+      _ => true,
+    }
+  }
+}
+
 /// MCU clock configuration.
+///
+/// `ClockConf::new` assumes a 2.7V to 3.6V supply.
 #[derive(Clone, Copy)]
 pub struct ClockConf {
   /// Clocking source.
   pub source: SystemClockSource,
+  /// Supply voltage range, used to derive flash wait states.
+  pub supply_voltage: SupplyVoltage,
 }
 
 /// MCU configuration.
@@ -110,6 +193,14 @@ impl SysConf {
 }
 
 impl ClockConf {
+  /// Returns a configuration for `source`, with a 2.7V to 3.6V supply.
+  pub const fn new(source: SystemClockSource) -> ClockConf {
+    ClockConf {
+      source: source,
+      supply_voltage: SupplyVoltage::SupplyVoltage2V7,
+    }
+  }
+
   fn setup(&self) {
     use self::SystemClockSource::*;
     use self::PLLClockSource::*;
@@ -124,17 +215,12 @@ impl ClockConf {
       },
       SystemClockHSE(freq) => {
         // Switch to HSE
-        if freq > 30_000_000 {
-          // this code doesn't support wait states configuration for HSE
-          unsafe { abort() };
-        } else {
-          self.enable_hse();
-          self.set_system_clock(reg::SystemClockSwitch::SystemClockHSE);
-          unsafe {
-            SystemClock = freq;
-            APBLowClock = freq;  // no divisor
-          };
-        }
+        self.enable_hse();
+        self.switch_system_clock(reg::SystemClockSwitch::SystemClockHSE, freq);
+        unsafe {
+          SystemClock = freq;
+          APBLowClock = freq;  // no divisor
+        };
       },
       SystemClockPLL(pll_conf) => {
         // Init and switch to pll
@@ -142,10 +228,7 @@ impl ClockConf {
           PLLClockHSE(_) => self.enable_hse(),
           _ => (),
         }
-        let sysfreq: u32 = match pll_conf.source {
-          PLLClockHSE(freq) => freq,
-          PLLClockHSI       => 16_000_000,
-        } as u32 * (pll_conf.n as u32 / pll_conf.m as u32) / pll_conf.p as u32;
+        let sysfreq: u32 = pll_conf.frequency();
         // system_stm32f4xx.c enables PWR and sets VOS to 1 here, but VOS
         // defaults to 1 so I see no real reason to do that.
         // peripheral_clock::PWRClock.enable();
@@ -155,9 +238,8 @@ impl ClockConf {
         let apb_low_divisor = 4;
         self.set_clock_divisors(1, apb_low_divisor, 2);
         pll_conf.setup();
-        // TODO(farcaller): this doesn't really belong here.
-        self.setup_flash(sysfreq);
-        self.set_system_clock(reg::SystemClockSwitch::SystemClockPLL);
+        self.switch_system_clock(reg::SystemClockSwitch::SystemClockPLL,
+                                 sysfreq);
         unsafe {
           SystemClock = sysfreq;
           APBLowClock = sysfreq / apb_low_divisor as u32;
@@ -166,21 +248,35 @@ impl ClockConf {
     }
   }
 
-  fn setup_flash(&self, freq: u32) {
+  /// Switches the system clock, keeping flash wait states sufficient for both
+  /// the old and the new frequency during the transition.
+  fn switch_system_clock(&self, clock: reg::SystemClockSwitch, freq: u32) {
+    let latency = self.supply_voltage.flash_latency(freq);
+    let raising = latency > reg::FLASH.ACR() & 0b1111;
+    if raising {
+      self.setup_flash(latency);
+    }
+    self.set_system_clock(clock);
+    wait_for!((reg::RCC.CFGR() >> 2) & 0b11 == clock as u32);
+    if !raising {
+      self.setup_flash(latency);
+    }
+  }
+
+  fn setup_flash(&self, latency: u32) {
+    let prefetch: u32 = if self.supply_voltage.prefetch_available() {
+      1 << 8
+    } else {
+      0
+    };
     reg::FLASH.set_ACR(
-      (1 << 8)  |  // enable prefetch
+      prefetch  |
       (1 << 9)  |  // enable instruction cache
       (1 << 10) |  // enable data cache
-      match freq/1_000_000 {  // wait states are calculated for 2.7-3.6V range
-        0...30    => 0,
-        31...60   => 1,
-        61...90   => 2,
-        90...120  => 3,
-        121...150 => 4,
-        151...168 => 5,
-        _        => unsafe { abort() },
-      }
+      latency
     );
+
+    wait_for!(reg::FLASH.ACR() & 0b1111 == latency);
   }
 
   fn enable_hse(&self) {
@@ -234,7 +330,44 @@ impl ClockConf {
   }
 }
 
+impl PLLClockSource {
+  /// Returns the PLL input frequency.
+  pub fn frequency(&self) -> u32 {
+    use self::PLLClockSource::*;
+    match *self {
+      PLLClockHSI       => 16_000_000,
+      PLLClockHSE(freq) => freq,
+    }
+  }
+}
+
 impl PLLConf {
+  /// Finds PLL parameters for the highest system clock that does not exceed
+  /// `target`, nor the maximum of `line`.
+  ///
+  /// If `usb` is set, only configurations that provide exactly 48MHz for USB,
+  /// SDIO and RNG are considered. Returns `None` if no configuration within
+  /// datasheet limits exists.
+  ///
+  /// STM32F4 has no platformtree support, so this is the way to configure the
+  /// clock by its target frequency.
+  pub fn for_frequency(line: ProductLine, source: PLLClockSource, target: u32,
+      usb: bool) -> Option<PLLConf> {
+    let limits = line.limits();
+    pll_solver::solve(source.frequency(), target, limits, usb).map(|s| PLLConf {
+      source: source,
+      m: s.m,
+      n: s.n,
+      p: s.p,
+      q: s.q,
+    })
+  }
+
+  /// Returns the system clock frequency this configuration produces.
+  pub fn frequency(&self) -> u32 {
+    self.source.frequency() / self.m as u32 * self.n as u32 / self.p as u32
+  }
+
   fn setup(&self) {
     use self::PLLClockSource::*;
 
@@ -21,6 +21,7 @@
 
 use super::init::reg;
 use core::marker::Copy;
+use hal::clock_gate::{ClockGate, GatedClock};
 
 use self::PeripheralClock::*;
 
@@ -107,7 +108,17 @@ pub enum PeripheralClock {
 
 impl Copy for PeripheralClock {}
 
+/// Number of users of each clock, indexed by variant.
+static mut CLOCK_USERS: [u8; 128] = [0; 128];
+
 impl PeripheralClock {
+  /// Returns a gate that keeps the clock enabled while it is alive.
+  ///
+  /// Prefer this over `enable` for clocks that might be shared.
+  pub fn gate(self) -> ClockGate<PeripheralClock> {
+    ClockGate::new(self)
+  }
+
   /// Enables the given peripheral clock.
   pub fn enable(self) {
     self.set_reg(true);
@@ -219,9 +230,43 @@ impl PeripheralClock {
       SDIOClock|SPI1Clock|SYSCFGClock|TIM9Clock|TIM10Clock|
       TIM11Clock => {
         let val = reg::RCC.APB2ENR();
-        reg::RCC.set_AHB1ENR((val & mask) | bit);
+        reg::RCC.set_APB2ENR((val & mask) | bit);
       },
     }
 
   }
+
+  /// Returns true if the clock is enabled.
+  fn is_enabled(self) -> bool {
+    let val = match self {
+      GPIOAClock|GPIOBClock|GPIOCClock|GPIODClock|GPIOEClock|GPIOFClock|
+      GPIOGClock|GPIOHClock|GPIOIClock|CRCClock|BKPSRAMClock|CCMDATARAMClock|
+      DMA1Clock|DMA2Clock|ETHMACClock|ETHMACTxClock|ETHMACRxClock|
+      ETHMACPTPClock|OTGHSClock|OTGHSULPIClock => reg::RCC.AHB1ENR(),
+      DCMIClock|CRYPClock|HASHClock|RNGClock|OTGFSClock => reg::RCC.AHB2ENR(),
+      FSMCClock => reg::RCC.AHB3ENR(),
+      TIM2Clock|TIM3Clock|TIM4Clock|TIM5Clock|TIM6Clock|TIM7Clock|TIM12Clock|
+      TIM13Clock|TIM14Clock|WWDGClock|SPI2Clock|SPI3Clock|USART2Clock|
+      USART3Clock|UART4Clock|UART5Clock|I2C1Clock|I2C2Clock|I2C3Clock|
+      CAN1Clock|CAN2Clock|PWRClock|DACClock => reg::RCC.APB1ENR(),
+      TIM1Clock|TIM8Clock|USART1Clock|USART6Clock|ADC1Clock|ADC2Clock|ADC3Clock|
+      SDIOClock|SPI1Clock|SYSCFGClock|TIM9Clock|TIM10Clock|
+      TIM11Clock => reg::RCC.APB2ENR(),
+    };
+    val & self.to_reg_bit() != 0
+  }
+}
+
+impl GatedClock for PeripheralClock {
+  unsafe fn users(self) -> &'static mut u8 {
+    &mut CLOCK_USERS[self as usize]
+  }
+
+  fn set_enabled(self, enabled: bool) {
+    self.set_reg(enabled);
+  }
+
+  fn enabled(self) -> bool {
+    self.is_enabled()
+  }
 }
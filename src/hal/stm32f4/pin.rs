@@ -18,9 +18,9 @@
 //! Some pins that could be configured here may be missing from actual MCU
 //! depending on the package.
 
+use hal::clock_gate::ClockGate;
 use hal::pin::{Gpio, GpioDirection, GpioLevel};
-use super::peripheral_clock;
-use core::intrinsics::abort;
+use super::peripheral_clock::PeripheralClock;
 
 use self::Port::*;
 
@@ -42,18 +42,75 @@ pub enum Port {
   PortI,
 }
 
-/// Pin functions.
+/// Pin output type.
 #[allow(missing_docs)]
 #[derive(Clone, Copy)]
-pub enum Function {
-  GPIOIn      = 0,
-  GPIOOut     = 1,
-  AltFunction = 2,
-  Analog      = 3,
+pub enum OutputType {
+  PushPull,
+  OpenDrain,
+}
+
+/// Pin pull resistors: up, down, or none.
+#[allow(missing_docs)]
+#[derive(Clone, Copy)]
+pub enum PullType {
+  PullNone,
+  PullUp,
+  PullDown,
+}
+
+/// Pin output speed.
+#[derive(Clone, Copy)]
+pub enum Speed {
+  /// 2 MHz
+  Low,
+  /// 25 MHz
+  Medium,
+  /// 50 MHz
+  Fast,
+  /// 100 MHz
+  High,
+}
+
+/// Alternate function selection, see the datasheet for the mapping of
+/// functions to pins.
+#[allow(missing_docs)]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+pub enum AltFunction {
+  Af0 = 0,
+  Af1 = 1,
+  Af2 = 2,
+  Af3 = 3,
+  Af4 = 4,
+  Af5 = 5,
+  Af6 = 6,
+  Af7 = 7,
+  Af8 = 8,
+  Af9 = 9,
+  Af10 = 10,
+  Af11 = 11,
+  Af12 = 12,
+  Af13 = 13,
+  Af14 = 14,
+  Af15 = 15,
+}
+
+/// Pin mode.
+#[derive(Clone, Copy)]
+pub enum Mode {
+  /// GPIO input.
+  GpioIn,
+  /// GPIO output.
+  GpioOut(OutputType, Speed),
+  /// Alternate function.
+  AltFunction(AltFunction, OutputType, Speed),
+  /// Analog mode, used by ADC and DAC.
+  Analog,
 }
 
 impl Port {
-  fn clock(self) -> peripheral_clock::PeripheralClock {
+  fn clock(self) -> PeripheralClock {
     use hal::stm32f4::peripheral_clock::PeripheralClock::*;
     match self {
       PortA => GPIOAClock,
@@ -67,38 +124,92 @@ impl Port {
       PortI => GPIOIClock,
     }
   }
+
+  fn reg(self) -> &'static reg::GPIO {
+    match self {
+      PortA => &reg::GPIO_A,
+      PortB => &reg::GPIO_B,
+      PortC => &reg::GPIO_C,
+      PortD => &reg::GPIO_D,
+      PortE => &reg::GPIO_E,
+      PortF => &reg::GPIO_F,
+      PortG => &reg::GPIO_G,
+      PortH => &reg::GPIO_H,
+      PortI => &reg::GPIO_I,
+    }
+  }
+
+  /// Sets and resets pins of the port in a single atomic write.
+  ///
+  /// Bits set in `set` drive the corresponding pins high, bits set in `reset`
+  /// drive them low. If a pin is in both masks, `set` takes priority.
+  pub fn set_reset(self, set: u16, reset: u16) {
+    self.reg().bsrr.set_bs(set as u32).set_br(reset as u32);
+  }
 }
 
-/// Pin configuration
-#[derive(Clone, Copy)]
+/// Pin configuration.
+///
+/// The port clock stays enabled while any pin of the port is alive. A pin is
+/// `Clone` but not `Copy`, a clone keeps the port clock enabled like the
+/// original.
+#[derive(Clone)]
 pub struct Pin {
   /// Pin port, mcu-specific.
   pub port: Port,
   /// Pin number.
   pub pin: u8,
-  /// Pin function, mcu-specific.
-  pub function: Function,
+  /// Port clock
+  #[allow(dead_code)]
+  clock: ClockGate<PeripheralClock>,
 }
 
 impl Pin {
-  /// Setup the pin.
+  /// Configures pin `pin` of `port` and returns it.
+  ///
+  /// Enables the port clock, then sets the pin up for `mode`: output type and
+  /// speed for outputs, and additionally the alternate function number for
+  /// `AltFunction`. The internal pull resistor is set to `pull`, which should
+  /// be `PullNone` for `Analog`.
   #[inline(always)]
-  pub fn setup(&self) {
-    use self::Function::*;
+  pub fn new(port: Port, pin: u8, mode: Mode, pull: PullType) -> Pin {
+    use self::Mode::*;
     use self::reg::GPIO_moder_mode as RegMode;
 
-    self.port.clock().enable();  // TODO(farcaller): should be done once per port
-
-    let offset = self.pin as usize;
-    let gpreg = self.get_reg();
+    let clock = port.clock().gate();
+    let gpreg = port.reg();
+    let offset = pin as usize;
 
-    let val = match self.function {
-      GPIOOut => RegMode::Output,
-      GPIOIn  => RegMode::Input,
-      _       => unsafe { abort() },  // FIXME(farcaller): not implemented
+    let val = match mode {
+      GpioIn => RegMode::Input,
+      GpioOut(otype, speed) => {
+        set_output(gpreg, offset, otype, speed);
+        RegMode::Output
+      },
+      AltFunction(af, otype, speed) => {
+        set_output(gpreg, offset, otype, speed);
+        if offset < 8 {
+          gpreg.afrl.set_afrl(offset, af as u32);
+        } else {
+          gpreg.afrh.set_afrh(offset - 8, af as u32);
+        }
+        RegMode::Alternate
+      },
+      Analog => RegMode::Analog,
     };
 
+    gpreg.pupdr.set_pupd(offset, match pull {
+      PullType::PullNone => reg::GPIO_pupdr_pupd::None,
+      PullType::PullUp   => reg::GPIO_pupdr_pupd::PullUp,
+      PullType::PullDown => reg::GPIO_pupdr_pupd::PullDown,
+    });
     gpreg.moder.set_mode(offset, val);
+
+    Pin {
+      port: port,
+      pin: pin,
+      clock: clock,
+    }
   }
 
   /// Toggles the GPIO value
@@ -110,31 +221,33 @@ impl Pin {
   }
 
   fn get_reg(&self) -> &reg::GPIO {
-    match self.port {
-      PortA => &reg::GPIO_A,
-      PortB => &reg::GPIO_B,
-      PortC => &reg::GPIO_C,
-      PortD => &reg::GPIO_D,
-      PortE => &reg::GPIO_E,
-      PortF => &reg::GPIO_F,
-      PortG => &reg::GPIO_G,
-      PortH => &reg::GPIO_H,
-      PortI => &reg::GPIO_I,
-    }
+    self.port.reg()
   }
 }
 
+fn set_output(gpreg: &reg::GPIO, offset: usize, otype: OutputType,
+    speed: Speed) {
+  gpreg.otyper.set_ot(offset, match otype {
+    OutputType::PushPull  => reg::GPIO_otyper_ot::PushPull,
+    OutputType::OpenDrain => reg::GPIO_otyper_ot::OpenDrain,
+  });
+  gpreg.ospeedr.set_ospeed(offset, match speed {
+    Speed::Low    => reg::GPIO_ospeedr_ospeed::Low,
+    Speed::Medium => reg::GPIO_ospeedr_ospeed::Medium,
+    Speed::Fast   => reg::GPIO_ospeedr_ospeed::Fast,
+    Speed::High   => reg::GPIO_ospeedr_ospeed::High,
+  });
+}
+
 impl Gpio for Pin {
   /// Sets output GPIO value to high.
   fn set_high(&self) {
-    let offset = self.pin as usize;
-    self.get_reg().bsrr.set_bs(offset, true);
+    self.get_reg().bsrr.set_bs(1 << self.pin as usize);
   }
 
   /// Sets output GPIO value to low.
   fn set_low(&self) {
-    let offset = self.pin as usize;
-    self.get_reg().bsrr.set_br(offset, true);
+    self.get_reg().bsrr.set_br(1 << self.pin as usize);
   }
 
   /// Returns input GPIO level.
@@ -149,9 +262,9 @@ impl Gpio for Pin {
   }
 
   /// Sets output GPIO direction.
+  ///
+  /// Output type, speed and pulls configured in `new` are preserved.
   fn set_direction(&self, new_mode: GpioDirection) {
-    // TODO(darayus): Verify that this works
-    // TODO(darayus): Change the Pin.function field to the new mode
     use self::reg::GPIO_moder_mode as RegMode;
     let offset = self.pin as usize;
     let reg = self.get_reg();
@@ -175,8 +288,8 @@ mod reg {
       0..31 => mode[16] {
         0 => Input,
         1 => Output,
-        3 => Alternate,
-        4 => Analog
+        2 => Alternate,
+        3 => Analog
       }
     }
     0x04 => reg32 otyper {
@@ -207,8 +320,8 @@ mod reg {
       0..15 => od[16]
     }
     0x18 => reg32 bsrr {
-      0..15 => bs[16]: wo,
-      16..31 => br[16]: wo
+      0..15 => bs: wo,
+      16..31 => br: wo
     }
     0x1c => reg32 lckr {
       0..15 => lck[16],
@@ -0,0 +1,192 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Main PLL parameter search.
+
+The tests run on the host with `cargo test --lib --features "test mcu_stm32f4"`.
+*/
+
+/// Lowest allowed VCO input frequency.
+const VCO_IN_MIN: u32 = 1_000_000;
+/// Highest allowed VCO input frequency.
+const VCO_IN_MAX: u32 = 2_000_000;
+/// Highest allowed VCO output frequency.
+const VCO_MAX: u32 = 432_000_000;
+/// Frequency required by USB OTG FS, SDIO and RNG.
+const USB_FREQ: u32 = 48_000_000;
+
+/// Limits that differ between the product lines.
+#[derive(Clone, Copy)]
+pub struct Limits {
+  /// Lowest allowed VCO output frequency.
+  pub vco_min: u32,
+  /// Highest allowed system clock frequency.
+  pub sysclk_max: u32,
+}
+
+/// Main PLL parameters and the resulting system clock frequency.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Solution {
+  /// Input division factor.
+  pub m: u8,
+  /// VCO multiplication factor.
+  pub n: u16,
+  /// System clock division factor.
+  pub p: u8,
+  /// USB, SDIO and RNG clock division factor.
+  pub q: u8,
+  /// System clock frequency the parameters produce.
+  pub frequency: u32,
+}
+
+/// Finds PLL parameters that produce the highest system clock not above
+/// `target`, given the `source` frequency and the `limits` of the part.
+///
+/// The VCO input is kept exact. If `usb` is set, only configurations that
+/// provide exactly 48MHz on the Q output are considered, otherwise it is just
+/// kept at or below 48MHz. Returns `None` if no valid configuration exists.
+pub fn solve(source: u32, target: u32, limits: Limits, usb: bool)
+    -> Option<Solution> {
+  let mut best: Option<Solution> = None;
+
+  for m in 2..64u32 {
+    if source % m != 0 || source / m < VCO_IN_MIN || source / m > VCO_IN_MAX {
+      continue;
+    }
+    let vco_in = source / m;
+    for n in 50..433u32 {
+      let vco = vco_in * n;
+      if vco < limits.vco_min {
+        continue;
+      }
+      if vco > VCO_MAX {
+        break;
+      }
+
+      let q = (vco + USB_FREQ - 1) / USB_FREQ;
+      if q < 2 || q > 15 || (usb && vco != q * USB_FREQ) {
+        continue;
+      }
+
+      for p in [2u32, 4, 6, 8].iter() {
+        let frequency = vco / *p;
+        if frequency > target || frequency > limits.sysclk_max {
+          continue;
+        }
+        let better = match best {
+          None => true,
+          Some(ref b) => frequency > b.frequency,
+        };
+        if better {
+          best = Some(Solution {
+            m: m as u8,
+            n: n as u16,
+            p: *p as u8,
+            q: q as u8,
+            frequency: frequency,
+          });
+        }
+      }
+    }
+  }
+
+  best
+}
+
+#[cfg(test)]
+mod test {
+  use super::{solve, Limits, Solution};
+
+  const F401: Limits = Limits { vco_min: 192_000_000, sysclk_max: 84_000_000 };
+  const F405: Limits = Limits { vco_min: 100_000_000, sysclk_max: 168_000_000 };
+  const F427: Limits = Limits { vco_min: 100_000_000, sysclk_max: 180_000_000 };
+
+  fn frequency(source: u32, s: &Solution) -> u32 {
+    source / s.m as u32 * s.n as u32 / s.p as u32
+  }
+
+  fn usb_frequency(source: u32, s: &Solution) -> u32 {
+    source / s.m as u32 * s.n as u32 / s.q as u32
+  }
+
+  #[test]
+  fn finds_maximum_frequency_from_hse() {
+    let s = solve(8_000_000, 168_000_000, F405, true).unwrap();
+    assert!(s.frequency == 168_000_000);
+    assert!(frequency(8_000_000, &s) == 168_000_000);
+    assert!(usb_frequency(8_000_000, &s) == 48_000_000);
+  }
+
+  #[test]
+  fn finds_maximum_frequency_from_hsi() {
+    let s = solve(16_000_000, 168_000_000, F405, false).unwrap();
+    assert!(s.frequency == 168_000_000);
+    assert!(frequency(16_000_000, &s) == 168_000_000);
+    assert!(usb_frequency(16_000_000, &s) <= 48_000_000);
+  }
+
+  #[test]
+  fn keeps_parameters_within_limits() {
+    let s = solve(25_000_000, 100_000_000, F405, false).unwrap();
+    let vco_in = 25_000_000 / s.m as u32;
+    assert!(25_000_000 % s.m as u32 == 0);
+    assert!(vco_in >= 1_000_000 && vco_in <= 2_000_000);
+    assert!(vco_in * s.n as u32 >= 100_000_000);
+    assert!(vco_in * s.n as u32 <= 432_000_000);
+    assert!(s.q >= 2 && s.q <= 15);
+  }
+
+  #[test]
+  fn rounds_down_to_closest_frequency() {
+    let s = solve(8_000_000, 99_999_999, F405, false).unwrap();
+    assert!(s.frequency <= 99_999_999);
+    assert!(s.frequency > 99_000_000);
+  }
+
+  #[test]
+  fn caps_frequency_at_maximum() {
+    let s = solve(8_000_000, 200_000_000, F405, false).unwrap();
+    assert!(s.frequency == 168_000_000);
+    let s = solve(8_000_000, 200_000_000, F427, false).unwrap();
+    assert!(s.frequency == 180_000_000);
+  }
+
+  #[test]
+  fn keeps_part_limits() {
+    let s = solve(8_000_000, 84_000_000, F401, false).unwrap();
+    assert!(s.frequency == 84_000_000);
+    assert!(8_000_000 / s.m as u32 * s.n as u32 >= 192_000_000);
+    let s = solve(8_000_000, 168_000_000, F401, false).unwrap();
+    assert!(s.frequency == 84_000_000);
+  }
+
+  #[test]
+  fn usb_restricts_frequencies() {
+    // 100MHz needs a VCO of 200, 400MHz, neither gives 48MHz.
+    let s = solve(8_000_000, 100_000_000, F405, true).unwrap();
+    assert!(s.frequency < 100_000_000);
+    assert!(usb_frequency(8_000_000, &s) == 48_000_000);
+  }
+
+  #[test]
+  fn rejects_unreachable_configuration() {
+    assert!(solve(8_000_000, 10_000_000, F405, false) ==
+        None::<Solution>);
+    assert!(solve(1_500_000, 168_000_000, F405, false) ==
+        None::<Solution>);
+    assert!(solve(8_000_000, 0, F405, false) == None::<Solution>);
+  }
+}
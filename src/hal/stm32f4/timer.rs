@@ -18,6 +18,7 @@
 //! This code supports only TIM2 at the moment.
 
 use super::peripheral_clock;
+use hal::clock_gate::ClockGate;
 use hal::timer;
 
 #[path="../../util/ioreg.rs"]
@@ -31,9 +32,14 @@ pub enum TimerPeripheral {
 }
 
 /// Structure describing a Timer.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Timer {
   reg: &'static reg::TIM2To5,
+  #[allow(dead_code)]
+  clock: ClockGate<peripheral_clock::PeripheralClock>,
 }
 
 impl Timer {
@@ -44,7 +50,7 @@ impl Timer {
       Timer2 => (peripheral_clock::PeripheralClock::TIM2Clock, &reg::TIM2),
     };
 
-    clock.enable();
+    let gate = clock.gate();
 
     reg.set_PSC(counter - 1);
     reg.set_CR1(1);
@@ -52,6 +58,7 @@ impl Timer {
 
     Timer {
       reg: reg,
+      clock: gate,
     }
   }
 }
@@ -70,11 +70,57 @@ pub struct PLLConf {
   pub q: u8,
 }
 
+/// MCU supply voltage range.
+///
+/// Flash access time depends on the supply voltage, so it determines the number
+/// of wait states required for a given clock.
+#[derive(Clone, Copy)]
+pub enum SupplyVoltage {
+  /// 1.8V to 2.1V, prefetch is not available.
+  SupplyVoltage1V8,
+  /// 2.1V to 2.4V.
+  SupplyVoltage2V1,
+  /// 2.4V to 2.7V.
+  SupplyVoltage2V4,
+  /// 2.7V to 3.6V.
+  SupplyVoltage2V7,
+}
+
+impl SupplyVoltage {
+  /// Returns the number of flash wait states required for given HCLK.
+  pub fn flash_latency(self, freq: u32) -> u32 {
+    use self::SupplyVoltage::*;
+    let step = match self {
+      SupplyVoltage1V8 => 20_000_000,
+      SupplyVoltage2V1 => 22_000_000,
+      SupplyVoltage2V4 => 24_000_000,
+      SupplyVoltage2V7 => 30_000_000,
+    };
+    let latency = if freq == 0 { 0 } else { (freq - 1) / step };
+    if freq > 216_000_000 || latency > 15 {
+      unsafe { abort() };
+    }
+    latency
+  }
+
+  /// Returns true if flash prefetch can be used in this range.
+  fn prefetch_available(self) -> bool {
+    match self {
+      SupplyVoltage::SupplyVoltage1V8 => false,
+      _ => true,
+    }
+  }
+}
+
 /// MCU clock configuration.
+///
+/// `ClockConf::new` assumes a 2.7V to 3.6V supply.
 #[derive(Clone, Copy)]
 pub struct ClockConf {
   /// Clocking source.
   pub source: SystemClockSource,
+  /// Supply voltage range, used to derive flash wait states.
+  pub supply_voltage: SupplyVoltage,
 }
 
 /// MCU configuration.
@@ -111,6 +157,14 @@ impl SysConf {
 }
 
 impl ClockConf {
+  /// Returns a configuration for `source`, with a 2.7V to 3.6V supply.
+  pub const fn new(source: SystemClockSource) -> ClockConf {
+    ClockConf {
+      source: source,
+      supply_voltage: SupplyVoltage::SupplyVoltage2V7,
+    }
+  }
+
   fn setup(&self) {
     use self::SystemClockSource::*;
     use self::PLLClockSource::*;
@@ -125,17 +179,12 @@ impl ClockConf {
       },
       SystemClockHSE(freq) => {
         // Switch to HSE
-        if freq > 30_000_000 {
-          // this code doesn't support wait states configuration for HSE
-          unsafe { abort() };
-        } else {
-          self.enable_hse();
-          self.set_system_clock(reg::SystemClockSwitch::SystemClockHSE);
-          unsafe {
-            SystemClock = freq;
-            APBLowClock = freq;  // no divisor
-          };
-        }
+        self.enable_hse();
+        self.switch_system_clock(reg::SystemClockSwitch::SystemClockHSE, freq);
+        unsafe {
+          SystemClock = freq;
+          APBLowClock = freq;  // no divisor
+        };
       },
       SystemClockPLL(pll_conf) => {
         // Init and switch to pll
@@ -146,7 +195,7 @@ impl ClockConf {
         let sysfreq: u32 = match pll_conf.source {
           PLLClockHSE(freq) => freq,
           PLLClockHSI       => 16_000_000,
-        } as u32 * (pll_conf.n as u32 / pll_conf.m as u32) / pll_conf.p as u32;
+        } as u32 / pll_conf.m as u32 * pll_conf.n as u32 / pll_conf.p as u32;
         // system_stm32f4xx.c enables PWR and sets VOS to 1 here, but VOS
         // defaults to 1 so I see no real reason to do that.
         // peripheral_clock::PWRClock.enable();
@@ -163,9 +212,8 @@ impl ClockConf {
           unsafe { abort() };
         }
 
-        // TODO(farcaller): this doesn't really belong here.
-        self.setup_flash(sysfreq);
-        self.set_system_clock(reg::SystemClockSwitch::SystemClockPLL);
+        self.switch_system_clock(reg::SystemClockSwitch::SystemClockPLL,
+                                 sysfreq);
         unsafe {
           SystemClock = sysfreq;
           APBLowClock = sysfreq / apb_low_divisor as u32;
@@ -180,21 +228,29 @@ impl ClockConf {
     reg::RCC.dckcfgr2.set_val(0);
   }
 
-  fn setup_flash(&self, freq: u32) {
+  /// Switches the system clock, keeping flash wait states sufficient for both
+  /// the old and the new frequency during the transition.
+  fn switch_system_clock(&self, clock: reg::SystemClockSwitch, freq: u32) {
+    let latency = self.supply_voltage.flash_latency(freq);
+    let raising = latency > reg::FLASH.acr.latency();
+    if raising {
+      self.setup_flash(latency);
+    }
+    self.set_system_clock(clock);
+    wait_for!(reg::RCC.cfgr.system_clock_status() == clock as u32);
+    if !raising {
+      self.setup_flash(latency);
+    }
+  }
+
+  fn setup_flash(&self, latency: u32) {
+    let prefetch = self.supply_voltage.prefetch_available();
     reg::FLASH.acr
       .set_art_on(true)
-      .set_prefetch_on(true)
-      .set_latency(match freq/1_000_000 {  // wait states are calculated for 2.7-3.6V range
-        0...30    => 0,
-        31...60   => 1,
-        61...90   => 2,
-        90...120  => 3,
-        121...150 => 4,
-        151...180 => 5,
-        181...210 => 6,
-        211...216 => 7,
-        _        => unsafe { abort() },
-      });
+      .set_prefetch_on(prefetch)
+      .set_latency(latency);
+
+    wait_for!(reg::FLASH.acr.latency() == latency);
   }
 
   fn enable_hse(&self) {
@@ -22,6 +22,7 @@
 
 use super::init::reg;
 use core::marker::Copy;
+use hal::clock_gate::{ClockGate, GatedClock};
 
 use self::PeripheralClock::*;
 
@@ -123,7 +124,17 @@ pub enum PeripheralClock {
 
 impl Copy for PeripheralClock {}
 
+/// Number of users of each clock, indexed by variant.
+static mut CLOCK_USERS: [u8; 128] = [0; 128];
+
 impl PeripheralClock {
+  /// Returns a gate that keeps the clock enabled while it is alive.
+  ///
+  /// Prefer this over `enable` for clocks that might be shared.
+  pub fn gate(self) -> ClockGate<PeripheralClock> {
+    ClockGate::new(self)
+  }
+
   /// Enables the given peripheral clock.
   pub fn enable(self) {
     self.set_reg(true);
@@ -250,4 +261,42 @@ impl PeripheralClock {
       },
     }
   }
+
+  /// Returns true if the clock is enabled.
+  fn is_enabled(self) -> bool {
+    let val = match self {
+      GPIOAClock|GPIOBClock|GPIOCClock|GPIODClock|GPIOEClock|GPIOFClock|
+      GPIOGClock|GPIOHClock|GPIOIClock|GPIOJClock|GPIOKClock|CRCClock|
+      BKPSRAMClock|DTCMClock|DMA1Clock|DMA2Clock|DMA2DClock|
+      ETHMACClock|ETHMACTxClock|ETHMACRxClock|
+      ETHMACPTPClock|OTGHSClock|OTGHSULPIClock => reg::RCC.ahb1enr.enable(),
+      DCMIClock|CRYPClock|HASHClock|RNGClock|OTGFSClock =>
+        reg::RCC.ahb2enr.enable(),
+      FSMCClock|QSPIClock => reg::RCC.ahb3enr.enable(),
+      TIM2Clock|TIM3Clock|TIM4Clock|TIM5Clock|TIM6Clock|TIM7Clock|TIM12Clock|
+      TIM13Clock|TIM14Clock|LPTIM1Clock|WWDGClock|SPI2Clock|SPI3Clock|SPDIFClock|
+      USART2Clock|USART3Clock|UART4Clock|UART5Clock|I2C1Clock|I2C2Clock|I2C3Clock|
+      I2C4Clock|CAN1Clock|CAN2Clock|CECClock|PWRClock|DACClock|UART7Clock|UART8Clock =>
+        reg::RCC.apb1enr.enable(),
+      TIM1Clock|TIM8Clock|USART1Clock|USART6Clock|ADC1Clock|ADC2Clock|ADC3Clock|
+      SDMMC1Clock|SPI1Clock|SYSCFGClock|TIM9Clock|TIM10Clock|TIM11Clock|
+      SPI5Clock|SPI6Clock|SAI1Clock|SAI2Clock|LTDCClock =>
+        reg::RCC.apb2enr.enable(),
+    };
+    val & self.to_reg_bit() != 0
+  }
+}
+
+impl GatedClock for PeripheralClock {
+  unsafe fn users(self) -> &'static mut u8 {
+    &mut CLOCK_USERS[self as usize]
+  }
+
+  fn set_enabled(self, enabled: bool) {
+    self.set_reg(enabled);
+  }
+
+  fn enabled(self) -> bool {
+    self.is_enabled()
+  }
 }
@@ -19,6 +19,7 @@
 //! This code supports only TIM2 at the moment.
 
 use super::peripheral_clock;
+use hal::clock_gate::ClockGate;
 use hal::timer;
 
 #[path="../../util/ioreg.rs"]
@@ -32,9 +33,14 @@ pub enum TimerPeripheral {
 }
 
 /// Structure describing a Timer.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Timer {
   reg: &'static reg::TIM,
+  #[allow(dead_code)]
+  clock: ClockGate<peripheral_clock::PeripheralClock>,
 }
 
 impl Timer {
@@ -45,7 +51,7 @@ impl Timer {
       Timer2 => (peripheral_clock::PeripheralClock::TIM2Clock, &reg::TIM2),
     };
 
-    clock.enable();
+    let gate = clock.gate();
 
     reg.psc.set_prescaler(counter - 1);
     reg.cr1.set_counter_enabled(true);
@@ -53,6 +59,7 @@ impl Timer {
 
     Timer {
       reg: reg,
+      clock: gate,
     }
   }
 }
@@ -24,28 +24,72 @@ use core::intrinsics::abort;
 use core::option::Option;
 use core::marker::Copy;
 
+use super::peripheral_clock::{PeripheralClock, BusApb1};
+use self::HseMode::*;
 use self::MsiSpeed::*;
+use self::PllClockSource::*;
 use self::SystemClockSource::*;
+use self::VoltageRange::*;
 
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
 
+/// High-speed external oscillator mode.
+#[derive(Clone, Copy)]
+pub enum HseMode {
+  /// Crystal or ceramic resonator with given frequency, 1-24MHz.
+  HseCrystal(u32),
+  /// External clock signal on OSC_IN with given frequency, up to 32MHz.
+  HseBypass(u32),
+}
+
+impl HseMode {
+  /// Returns the HSE frequency.
+  pub fn frequency(&self) -> u32 {
+    match *self {
+      HseCrystal(freq) => freq,
+      HseBypass(freq) => freq,
+    }
+  }
+
+  /// Starts the oscillator and waits for it to stabilize.
+  fn enable(&self) {
+    let r = &reg::RCC;
+    release_system_clock();
+    r.cr.set_hse_on(false);
+    wait_for!(!r.cr.hse_ready());
+    r.cr.set_hse_bypass(match *self {
+      HseCrystal(_) => false,
+      HseBypass(_) => true,
+    });
+    r.cr.set_hse_on(true);
+    wait_for!(r.cr.hse_ready());
+  }
+}
+
 /// Phase-locked loop clock source.
-#[repr(u8)]
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub enum PllClockSource {
   /// Takes base clock from HSI.
-  PllSourceHSI = 0,
+  PllSourceHSI,
   /// Takes base clock from HSE.
-  PllSourceHSE = 1,
+  PllSourceHSE(HseMode),
 }
 
-impl Copy for PllClockSource {}
+impl PllClockSource {
+  /// Returns the PLL input frequency.
+  pub fn frequency(&self) -> u32 {
+    match *self {
+      PllSourceHSI => 16_000_000,
+      PllSourceHSE(hse) => hse.frequency(),
+    }
+  }
+}
 
 /// PLL multiplier: 3, 4, 6, 8, 12, 16, 24, 32, 48
 pub type PllMultiplier = u8;
 
-/// PLL divisor: 1, 2, 3, 4
+/// PLL divisor: 2, 3, 4
 pub type PllDivisor = u8;
 
 /// Multi-speed internal clock divisor.
@@ -78,7 +122,7 @@ pub enum SystemClockSource {
   /// High-speed internal oscillator, 16MHz.
   SystemClockHSI,
   /// High-speed external oscillator with configurable frequency.
-  SystemClockHSE(u32),
+  SystemClockHSE(HseMode),
   /// PLL.
   SystemClockPLL(PllClockSource, PllMultiplier, PllDivisor),
 }
@@ -101,14 +145,77 @@ impl SystemClockSource {
         SystemClockMSI(Msi2097) => 2_097_000,
         SystemClockMSI(Msi4194) => 4_194_000,
         SystemClockHSI => 16_000_000,
-        SystemClockHSE(_) => unsafe { abort() }, //TODO(kvark)
-        SystemClockPLL(_, _, _) => unsafe { abort() }, //TODO(kvark)
+        SystemClockHSE(hse) => hse.frequency(),
+        SystemClockPLL(source, mul, div) =>
+          source.frequency() * mul as u32 / div as u32,
+    }
+  }
+
+  /// Returns the lowest-power voltage range the source can operate in.
+  pub fn voltage_range(&self) -> VoltageRange {
+    let (freq, vco) = match *self {
+      SystemClockPLL(source, mul, _) =>
+        (self.frequency(), source.frequency() * mul as u32),
+      _ => (self.frequency(), 0),
+    };
+    if freq > 16_000_000 || vco > 48_000_000 {
+      VoltageRange1
+    } else if freq > 4_200_000 || vco > 0 {
+      VoltageRange2
+    } else {
+      VoltageRange3
     }
   }
 }
 
 impl Copy for SystemClockSource {}
 
+/// Internal regulator voltage range.
+///
+/// The core voltage limits the maximum system and PLL VCO frequency.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum VoltageRange {
+  /// 1.8V, system clock up to 32MHz, PLL VCO up to 96MHz.
+  VoltageRange1 = 1,
+  /// 1.5V, system clock up to 16MHz, PLL VCO up to 48MHz.
+  VoltageRange2 = 2,
+  /// 1.2V, system clock up to 4.2MHz, no PLL.
+  VoltageRange3 = 3,
+}
+
+impl VoltageRange {
+  /// Returns the highest system clock frequency that runs from flash without
+  /// a wait state in this range.
+  fn zero_wait_state_frequency(self) -> u32 {
+    match self {
+      VoltageRange1 => 16_000_000,
+      VoltageRange2 => 8_000_000,
+      VoltageRange3 => 2_100_000,
+    }
+  }
+
+  /// Switches the regulator to this range and waits for it to settle.
+  ///
+  /// PWR clock must be enabled.
+  fn apply(self) {
+    wait_for!(!reg::PWR.csr.voltage_scaling_busy());
+    reg::PWR.cr.set_voltage_scaling(self as u32);
+    wait_for!(!reg::PWR.csr.voltage_scaling_busy());
+  }
+
+  /// Returns the range the regulator is currently set to.
+  ///
+  /// PWR clock must be enabled.
+  fn current() -> VoltageRange {
+    match reg::PWR.cr.voltage_scaling() {
+      1 => VoltageRange1,
+      2 => VoltageRange2,
+      _ => VoltageRange3,
+    }
+  }
+}
+
 #[allow(missing_docs)]
 #[repr(u8)]
 #[derive(Clone)]
@@ -171,9 +278,29 @@ impl ClockConfig {
   }
 
   /// Set this configuration on the hardware.
+  ///
+  /// The voltage range and flash wait state are derived from the system clock
+  /// frequency. If HSE or PLL drive the system clock and have to be
+  /// reconfigured, it runs from MSI meanwhile.
   pub fn setup(&self) {
     let r = &reg::RCC;
 
+    // The voltage range is kept with the PWR clock off. A PWR clock that was
+    // on before is left on when the gate is dropped.
+    let _pwr = PeripheralClock::Apb1(BusApb1::Pwr).gate();
+    let range = self.source.voltage_range();
+    let wait_state = self.source.frequency() > range.zero_wait_state_frequency();
+
+    // A faster clock needs the higher core voltage and the flash wait state in
+    // place before the switch, while lowering them is only safe after it.
+    let previous_range = VoltageRange::current();
+    if range < previous_range {
+      range.apply();
+    }
+    if wait_state {
+      set_flash_wait_state(true);
+    }
+
     let source_type = match self.source {
       SystemClockMSI(msi) => {
         r.cr.set_msi_on(true);
@@ -186,16 +313,22 @@ impl ClockConfig {
         wait_for!(r.cr.hsi_ready());
         1
       },
-      SystemClockHSE(_) => {
-        r.cr.set_hse_on(true);
-        wait_for!(r.cr.hse_ready());
-        //TODO(kvark): HSE config
+      SystemClockHSE(hse) => {
+        hse.enable();
         2
       },
       SystemClockPLL(pll_source, mul, div) => {
-        r.cr.set_pll_on(true);
-        wait_for!(r.cr.pll_ready());
-        r.cfgr.set_pll_clock_source(pll_source as usize != 0);
+        let source_bit = match pll_source {
+          PllSourceHSI => {
+            r.cr.set_hsi_on(true);
+            wait_for!(r.cr.hsi_ready());
+            false
+          },
+          PllSourceHSE(hse) => {
+            hse.enable();
+            true
+          },
+        };
         let factor = match mul {
           3 => 0,
           4 => 1,
@@ -208,8 +341,22 @@ impl ClockConfig {
           48 => 8,
           _ => unsafe { abort() } // not supported
         };
+        if div < 2 || div > 4 {
+          unsafe { abort() } // not supported
+        }
+        if pll_source.frequency() * mul as u32 > 96_000_000 {
+          unsafe { abort() } // VCO frequency is out of range
+        }
+
+        // PLL can only be configured while it is stopped.
+        release_system_clock();
+        r.cr.set_pll_on(false);
+        wait_for!(!r.cr.pll_ready());
+        r.cfgr.set_pll_clock_source(source_bit);
         r.cfgr.set_pll_mul_factor(factor);
-        r.cfgr.set_pll_output_div(div as u32);
+        r.cfgr.set_pll_output_div(div as u32 - 1);
+        r.cr.set_pll_on(true);
+        wait_for!(r.cr.pll_ready());
         3
       }
     };
@@ -217,6 +364,13 @@ impl ClockConfig {
     r.cfgr.set_system_clock(source_type);
     wait_for!(r.cfgr.system_clock_status() == source_type);
 
+    if !wait_state {
+      set_flash_wait_state(false);
+    }
+    if range > previous_range {
+      range.apply();
+    }
+
     if self.ahb_shift > 9 || self.apb1_shift > 4 || self.apb2_shift > 4 {
       unsafe { abort() } // not supported
     }
@@ -254,6 +408,43 @@ impl ClockConfig {
   }
 }
 
+/// Enables or disables the flash wait state.
+///
+/// 64-bit flash access has to be on whenever a wait state is used, and is
+/// toggled in the order required by the reference manual.
+/// Switches the system clock to MSI if it runs from HSE or PLL, as they can't
+/// be stopped otherwise.
+///
+/// MSI runs at 4.2MHz at most, which works in every voltage range with the
+/// flash wait state.
+fn release_system_clock() {
+  let r = &reg::RCC;
+  if r.cfgr.system_clock_status() < 2 {
+    return;
+  }
+  set_flash_wait_state(true);
+  r.cr.set_msi_on(true);
+  wait_for!(r.cr.msi_ready());
+  r.cfgr.set_system_clock(0);
+  wait_for!(r.cfgr.system_clock_status() == 0);
+}
+
+fn set_flash_wait_state(enable: bool) {
+  let acr = &reg::FLASH.acr;
+  if enable {
+    acr.set_access_64bit(true);
+    wait_for!(acr.access_64bit());
+    acr.set_latency(true);
+    wait_for!(acr.latency());
+    acr.set_prefetch(true);
+  } else {
+    acr.set_prefetch(false);
+    acr.set_latency(false);
+    wait_for!(!acr.latency());
+    acr.set_access_64bit(false);
+  }
+}
+
 // TODO(farcaller): this mod is pub as it's being used in peripheral_clock.rs.
 //                  This is not the best design solution and a good reason to
 //                  split RCC into distinct registers.
@@ -349,7 +540,11 @@ pub mod reg {
 
   ioregs!(FLASH = {
     0x00 => reg32 acr {     // access control
-      31..0 => access_control : rw,
+      0 => latency : rw,
+      1 => prefetch : rw,
+      2 => access_64bit : rw,
+      3 => sleep_power_down : rw,
+      4 => run_power_down : rw,
     },
     0x04 => reg32 pecr {    // program/erase control
       31..0 => program_control : rw,
@@ -385,10 +580,28 @@ pub mod reg {
 
   ioregs!(PWR = {
     0x0 => reg32 cr {   // power control
-      31..0 => control : rw,
+      0 => low_power_deep_sleep : rw,
+      1 => power_down_deep_sleep : rw,
+      2 => clear_wakeup : rw,
+      3 => clear_standby : rw,
+      4 => voltage_detector_on : rw,
+      7..5 => voltage_detector_level : rw,
+      8 => disable_backup_protection : rw,
+      9 => ultra_low_power : rw,
+      10 => fast_wakeup : rw,
+      12..11 => voltage_scaling : rw,
+      14 => low_power_run : rw,
     },
     0x4 => reg32 csr {  // power control/status
-      31..0 => status : rw,
+      0 => wakeup : ro,
+      1 => standby : ro,
+      2 => voltage_detector_output : ro,
+      3 => vrefint_ready : ro,
+      4 => voltage_scaling_busy : ro,
+      5 => regulator_low_power : ro,
+      8 => enable_wakeup_pin1 : rw,
+      9 => enable_wakeup_pin2 : rw,
+      10 => enable_wakeup_pin3 : rw,
     },
   });
 
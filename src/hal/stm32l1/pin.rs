@@ -19,6 +19,7 @@
 //! depending on the package.
 
 use super::peripheral_clock;
+use hal::clock_gate::ClockGate;
 use core::intrinsics::abort;
 use self::Port::*;
 
@@ -105,12 +106,19 @@ pub enum Mode {
 }
 
 /// Pin configuration.
-#[derive(Clone, Copy)]
+///
+/// The port clock stays enabled while any pin of the port is alive. A pin is
+/// `Clone` but not `Copy`, a clone keeps the port clock enabled like the
+/// original.
+#[derive(Clone)]
 pub struct Pin {
   /// Pin index.
   pub index: u8,
   /// GPIO register
   reg: &'static reg::GPIO,
+  /// Port clock
+  #[allow(dead_code)]
+  clock: ClockGate<peripheral_clock::PeripheralClock>,
 }
 
 impl Pin {
@@ -129,8 +137,7 @@ impl Pin {
       PortG => (&reg::GPIOG, clock::GpioG),
       PortH => (&reg::GPIOH, clock::GpioH),
     };
-    // TODO(farcaller): should be done once per port
-    peripheral_clock::PeripheralClock::Ahb(clock).enable();
+    let clock = peripheral_clock::PeripheralClock::Ahb(clock).gate();
 
     let offset1 = pin_index as usize;
     let mask1 = !(0b1u16 << offset1);
@@ -183,6 +190,7 @@ impl Pin {
     Pin {
       index: pin_index,
       reg: reg,
+      clock: clock,
     }
   }
 }
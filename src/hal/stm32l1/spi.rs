@@ -19,6 +19,11 @@ use core::result::Result;
 use core::result::Result::{Ok, Err};
 use core::marker::Copy;
 
+use hal::clock_gate::ClockGate;
+use hal::cortex_m3::nvic;
+use hal::stm32l1::peripheral_clock::PeripheralClock;
+use os::executor::{Future, Poll, Waker, WakerSlot};
+
 #[path="../../util/wait_for.rs"]
 #[macro_use] mod wait_for;
 
@@ -101,9 +106,15 @@ pub enum Error {
 }
 
 /// Structure describing a SPI instance.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Spi {
+  peripheral: Peripheral,
   reg: &'static reg::SPI,
+  #[allow(dead_code)]
+  clock: ClockGate<PeripheralClock>,
 }
 
 impl Spi {
@@ -119,7 +130,7 @@ impl Spi {
       Peripheral::Spi3 => (&reg::SPI3, clock::Apb1(clock::BusApb1::Spi3)),
     };
 
-    clock.enable();
+    let gate = clock.gate();
 
     // set direction
     reg.cr1.set_receive_only(direction == Direction::RxOnly);
@@ -156,7 +167,9 @@ impl Spi {
     }else {
       reg.cr1.set_spi_enable(true);
       Ok(Spi {
+        peripheral: peripheral,
         reg: reg,
+        clock: gate,
       })
     }
   }
@@ -193,6 +206,105 @@ impl Spi {
   }
 }
 
+impl Spi {
+  /// Returns a future that transfers `tx`, storing the bytes received in
+  /// `rx` as far as it is long.
+  ///
+  /// Only one transfer per SPI may wait at a time. The SPI's ISR must call
+  /// `on_interrupt`.
+  pub fn transfer_async<'a>(&'a self, tx: &'a [u8], rx: &'a mut [u8])
+      -> TransferFuture<'a> {
+    TransferFuture { spi: self, tx: tx, rx: rx, pos: 0, sent: false }
+  }
+}
+
+impl Peripheral {
+  fn irq(self) -> usize {
+    match self {
+      Peripheral::Spi1 => 35,
+      Peripheral::Spi2 => 36,
+      Peripheral::Spi3 => 47,
+    }
+  }
+}
+
+/// Future returned by `Spi::transfer_async`.
+pub struct TransferFuture<'a> {
+  spi: &'a Spi,
+  tx: &'a [u8],
+  rx: &'a mut [u8],
+  pos: usize,
+  /// Whether `tx[pos]` was written and its answer is awaited.
+  sent: bool,
+}
+
+impl<'a> TransferFuture<'a> {
+  /// Waits for an interrupt, returns false if there is no need to because
+  /// the status bit got set meanwhile.
+  fn wait(&self, waker: &Waker) -> bool {
+    let reg = self.spi.reg;
+    Wakers[self.spi.peripheral as usize].register(waker);
+    if self.sent {
+      reg.cr2.set_rx_buffer_not_empty_interrupt_enable(true);
+    } else {
+      reg.cr2.set_tx_buffer_empty_interrupt_enable(true);
+    }
+    nvic::enable_irq(self.spi.peripheral.irq());
+    if self.sent {
+      !reg.sr.receive_buffer_not_empty()
+    } else {
+      !reg.sr.transmit_buffer_empty()
+    }
+  }
+}
+
+impl<'a> Future for TransferFuture<'a> {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    let reg = self.spi.reg;
+    while self.pos < self.tx.len() {
+      if !self.sent {
+        if !reg.sr.transmit_buffer_empty() && self.wait(waker) {
+          return Poll::Pending;
+        }
+        reg.dr.set_data(self.tx[self.pos] as u16);
+        self.sent = true;
+      }
+      if !reg.sr.receive_buffer_not_empty() && self.wait(waker) {
+        return Poll::Pending;
+      }
+      let value = reg.dr.data() as u8;
+      if self.pos < self.rx.len() {
+        self.rx[self.pos] = value;
+      }
+      self.pos += 1;
+      self.sent = false;
+    }
+    Poll::Ready(())
+  }
+}
+
+/// Wakes the transfer waiting on `peripheral`.
+///
+/// Must be called from the SPI's ISR when using `transfer_async`.
+pub fn on_interrupt(peripheral: Peripheral) {
+  let reg = match peripheral {
+    Peripheral::Spi1 => &reg::SPI1,
+    Peripheral::Spi2 => &reg::SPI2,
+    Peripheral::Spi3 => &reg::SPI3,
+  };
+  // The transfer enables the interrupts again when it needs to wait.
+  reg.cr2.set_rx_buffer_not_empty_interrupt_enable(false);
+  reg.cr2.set_tx_buffer_empty_interrupt_enable(false);
+  Wakers[peripheral as usize].wake();
+}
+
+/// Wakers of transfers, indexed by `Peripheral`.
+#[allow(non_upper_case_globals)]
+static Wakers: [WakerSlot; 3] =
+    [WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
 impl ::hal::spi::Spi for Spi {
   fn write(&self, value: u8) {
     wait_for!(self.reg.sr.transmit_buffer_empty());
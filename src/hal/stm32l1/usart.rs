@@ -23,8 +23,10 @@ use core::result::Result;
 use core::intrinsics::abort;
 
 use drivers::chario::CharIO;
+use hal::clock_gate::ClockGate;
 use hal::uart;
 use hal::stm32l1::init;
+use hal::stm32l1::peripheral_clock::PeripheralClock;
 
 use self::UsartPeripheral::*;
 
@@ -67,9 +69,14 @@ pub enum StopBit {
 }
 
 /// Structure describing a USART instance.
-#[derive(Clone, Copy)]
+///
+/// It keeps the peripheral clock enabled while alive, so it is `Clone` but
+/// not `Copy`, a clone keeps the clock enabled like the original.
+#[derive(Clone)]
 pub struct Usart {
   reg: &'static reg::USART,
+  #[allow(dead_code)]
+  clock: ClockGate<PeripheralClock>,
 }
 
 impl Usart {
@@ -77,7 +84,6 @@ impl Usart {
   pub fn new(peripheral: UsartPeripheral, baudrate: u32, word_len: WordLen,
              parity: uart::Parity, stop_bits: StopBit,
              config: &init::ClockConfig) -> Usart {
-    use hal::stm32l1::peripheral_clock::PeripheralClock;
     use hal::stm32l1::peripheral_clock as clock;
     use hal::uart::Parity::*;
 
@@ -89,7 +95,7 @@ impl Usart {
         Uart5  => (&reg::UART5,  PeripheralClock::Apb1(clock::BusApb1::Uart5)),
     };
 
-    clock.enable();
+    let gate = clock.gate();
 
     reg.cr1.set_word_length(word_len as usize != 0);
     reg.cr2.set_stop_bits(stop_bits as u16);
@@ -120,6 +126,7 @@ impl Usart {
 
     Usart {
       reg: reg,
+      clock: gate,
     }
   }
 }
@@ -17,33 +17,38 @@
 
 pub use os::cond_var::internal::{CondVar, COND_VAR_INIT};
 
-#[cfg(multitasking)]
+#[cfg(feature = "multitasking")]
 mod internal {
-  use core::option::{None, Some};
-  use core::ty::Unsafe;
   use core::marker::Sync;
-
-  use hal::cortex_m3::sched::NoInterrupts;
-  use util::queue::{Queue, Node};
-  use os::task::{TaskDescriptor, Tasks};
+  use core::option::Option::{Some, None};
+
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+  use os::task::{WaitList, wait, wake_one};
+  use os::time::{Duration, Instant};
+  use os::trace;
+  use util::queue::Queue;
 
   /// A condition variable
   pub struct CondVar {
-    waiting: Queue<*mut TaskDescriptor>
+    waiting: WaitList,
   }
 
   /// Static initializer
   pub const COND_VAR_INIT: CondVar = CondVar {
-    waiting: Queue {
-      head: Unsafe { value: 0 as *mut Node<*mut TaskDescriptor>, marker1: marker::InvariantType },
-      tail: Unsafe { value: 0 as *mut Node<*mut TaskDescriptor>, marker1: marker::InvariantType },
-    }
+    waiting: Queue::new(),
   };
 
   impl CondVar {
     /// Create a new condition variable
     pub fn new() -> CondVar {
-      CondVar { waiting: Queue::new() }
+      COND_VAR_INIT
     }
 
     /// Wait on a condition variable.
@@ -55,9 +60,18 @@ mod internal {
        */
       unsafe {
         let crit = NoInterrupts::new();
-        let mut waiting = Node::new(Tasks.current_task() as *mut TaskDescriptor);
-        self.waiting.push(&mut waiting, &crit);
-        Tasks.current_task().block(crit);
+        trace::record(trace::Kind::CondVarBlock, self as *const CondVar as u32);
+        wait(&self.waiting, None, crit);
+      }
+    }
+
+    /// Wait on a condition variable for at most `timeout`. Returns false if
+    /// it timed out.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+      unsafe {
+        let crit = NoInterrupts::new();
+        trace::record(trace::Kind::CondVarBlock, self as *const CondVar as u32);
+        wait(&self.waiting, Some(Instant::now() + timeout), crit)
       }
     }
 
@@ -65,10 +79,7 @@ mod internal {
     pub fn signal(&self) {
       unsafe {
         let crit = NoInterrupts::new();
-        match self.waiting.pop(&crit) {
-          None => { },
-          Some(task) => (*(*task).data).unblock(&crit)
-        }
+        wake_one(&self.waiting, &crit);
       }
     }
 
@@ -76,24 +87,20 @@ mod internal {
     pub fn broadcast(&self) {
       unsafe {
         let crit = NoInterrupts::new();
-        loop {
-          match self.waiting.pop(&crit) {
-            None => break,
-            Some(task) => (*(*task).data).unblock(&crit)
-          }
-        }
+        while wake_one(&self.waiting, &crit) {}
       }
     }
   }
 
-  impl Sync for CondVar {}
+  unsafe impl Sync for CondVar {}
 }
 
-#[cfg(not(multitasking))]
+#[cfg(not(feature = "multitasking"))]
 mod internal {
   use core::marker::Sync;
   use core::cell::UnsafeCell;
 
+  use os::time::Duration;
   use util::support::wfi;
 
   /// A condition variable
@@ -125,6 +132,15 @@ mod internal {
       }
     }
 
+    /// Wait on a condition variable for at most `timeout`. Returns false if
+    /// it timed out.
+    ///
+    /// Without tasks, there is no time base to wait against, so this gives up
+    /// right away, like a zero `timeout`.
+    pub fn wait_timeout(&self, _: Duration) -> bool {
+      false
+    }
+
     /// Wake up a thread waiting on a condition variable.
     pub fn signal(&self) {
       unsafe {
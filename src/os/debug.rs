@@ -14,19 +14,156 @@
 // limitations under the License.
 
 //! Tracing for debugging.
-//
-// Use `set_backend`
+//!
+//! Output goes to the backend set with `set_backend`, and is only produced
+//! when built with `--cfg debug`.
+//! `drivers::rtt` and `drivers::semihosting` provide backends that need only
+//! the debug probe.
+//!
+//! Messages are logged at a `Level` with the `zerror!`, `zwarn!`, `zinfo!`,
+//! `zdebug!` and `ztrace!` macros, which take `format!` style arguments:
+//!
+//! ```ignore
+//! zinfo!("sent {} bytes", len);
+//! ```
+//!
+//! The `z` prefix keeps them apart from the macros of the `log` crate.
+//!
+//! Messages above the level set with `set_level` are dropped, `set_filter`
+//! overrides it for modules whose path starts with the given prefix.
+//!
+//! `RingBuffer` is a backend that only stores the output, so logging never
+//! waits for the UART. Its write hook starts the UART TX interrupt, whose
+//! handler drains it:
+//!
+//! ```ignore
+//! static LOG: RingBuffer<[u8; 256]> = RingBuffer::new([0; 256]);
+//!
+//! fn start_tx() {
+//!   // Enable the UART TX interrupt.
+//! }
+//!
+//! LOG.set_write_hook(start_tx);
+//! let _token = debug::set_backend(&LOG);
+//! ...
+//! // In the UART TX interrupt, disabling it once LOG is empty.
+//! LOG.drain_to(&uart, 16);
+//! ```
+
+use core::option::Option::{self, Some, None};
+
+pub use os::debug::internal::{set_backend, print, print_hex, Token};
+pub use os::debug::internal::{set_level, set_filter, enabled, log};
+#[cfg(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub use os::debug::ring::RingBuffer;
+
+/// Importance of a log message.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+  /// Something failed.
+  Error = 1,
+  /// Something unexpected happened, but was handled.
+  Warn,
+  /// Normal operation.
+  Info,
+  /// Details useful for debugging.
+  Debug,
+  /// Very verbose details.
+  Trace,
+}
+
+impl Level {
+  #[cfg_attr(not(debug), allow(dead_code))]
+  fn tag(self) -> &'static str {
+    match self {
+      Level::Error => "E",
+      Level::Warn  => "W",
+      Level::Info  => "I",
+      Level::Debug => "D",
+      Level::Trace => "T",
+    }
+  }
+}
+
+/// Logs a message at the given level, with `format!` style arguments.
+#[macro_export]
+macro_rules! zlog {
+  ($level:expr, $($arg:tt)+) => ({
+    let level = $level;
+    if $crate::os::debug::enabled(level, module_path!()) {
+      $crate::os::debug::log(level, module_path!(), format_args!($($arg)+));
+    }
+  })
+}
+
+/// Logs a message at `Level::Error`.
+#[macro_export]
+macro_rules! zerror {
+  ($($arg:tt)+) => (zlog!($crate::os::debug::Level::Error, $($arg)+))
+}
+
+/// Logs a message at `Level::Warn`.
+#[macro_export]
+macro_rules! zwarn {
+  ($($arg:tt)+) => (zlog!($crate::os::debug::Level::Warn, $($arg)+))
+}
+
+/// Logs a message at `Level::Info`.
+#[macro_export]
+macro_rules! zinfo {
+  ($($arg:tt)+) => (zlog!($crate::os::debug::Level::Info, $($arg)+))
+}
+
+/// Logs a message at `Level::Debug`.
+#[macro_export]
+macro_rules! zdebug {
+  ($($arg:tt)+) => (zlog!($crate::os::debug::Level::Debug, $($arg)+))
+}
+
+/// Logs a message at `Level::Trace`.
+#[macro_export]
+macro_rules! ztrace {
+  ($($arg:tt)+) => (zlog!($crate::os::debug::Level::Trace, $($arg)+))
+}
 
-pub use os::debug::internal::{set_backend, print, Token};
+/// Number of module filters that can be set.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(debug), allow(dead_code))]
+const MaxFilters: usize = 8;
+
+/// Returns the level of the longest filter matching `module`.
+#[cfg_attr(not(debug), allow(dead_code))]
+fn filter_level(filters: &[Option<(&'static str, Level)>], module: &str)
+    -> Option<Level> {
+  let mut best: Option<(&'static str, Level)> = None;
+  for filter in filters.iter() {
+    match *filter {
+      Some((prefix, level)) if module.starts_with(prefix) => {
+        match best {
+          Some((best_prefix, _)) if best_prefix.len() >= prefix.len() => {},
+          _ => best = Some((prefix, level)),
+        }
+      },
+      _ => {},
+    }
+  }
+  best.map(|(_, level)| level)
+}
 
 #[cfg(debug)]
 mod internal {
   use core::option::{Some, None, Option};
   use core::ops::Drop;
   use core::mem::transmute;
-  use drivers::chario::CharIO;
+  use core::fmt::{Arguments, Write};
+  use drivers::chario::{CharIO, Writer};
+  use super::{Level, MaxFilters, filter_level};
 
   static mut backend: Option<*const CharIO + 'static> = None;
+  static mut max_level: Level = Level::Info;
+  static mut filters: [Option<(&'static str, Level)>; MaxFilters] =
+      [None; MaxFilters];
 
   /// A token to ensure the life of the reference to the debugging output backend
   /// doesn't outlive the backend itself.
@@ -62,11 +199,75 @@ mod internal {
       }
     }
   }
+
+  /// Print a number in hex to the debugging output backend
+  pub fn print_hex(i: u32) {
+    unsafe {
+      match backend {
+        Some(b) => (*b).puth(i),
+        None => {},
+      }
+    }
+  }
+
+  /// Sets the most verbose level that is logged, `Level::Info` by default.
+  pub fn set_level(level: Level) {
+    unsafe { max_level = level };
+  }
+
+  /// Sets the most verbose level that is logged for modules whose path starts
+  /// with `prefix`, overriding `set_level`.
+  ///
+  /// Returns false if all filter slots are taken.
+  pub fn set_filter(prefix: &'static str, level: Level) -> bool {
+    unsafe {
+      for filter in filters.iter_mut() {
+        match *filter {
+          Some((p, _)) if p == prefix => {
+            *filter = Some((prefix, level));
+            return true;
+          },
+          _ => {},
+        }
+      }
+      for filter in filters.iter_mut() {
+        if filter.is_none() {
+          *filter = Some((prefix, level));
+          return true;
+        }
+      }
+      false
+    }
+  }
+
+  /// Returns true if messages at `level` from `module` are logged.
+  pub fn enabled(level: Level, module: &str) -> bool {
+    unsafe {
+      backend.is_some() &&
+          level <= filter_level(&filters, module).unwrap_or(max_level)
+    }
+  }
+
+  /// Logs a message, use the logging macros instead.
+  #[doc(hidden)]
+  pub fn log(level: Level, module: &str, args: Arguments) {
+    unsafe {
+      match backend {
+        Some(b) => {
+          let _ = write!(Writer(&*b), "[{} {}] {}\n", level.tag(), module,
+              args);
+        },
+        None => {},
+      }
+    }
+  }
 }
 
 #[cfg(not(debug))]
 mod internal {
+  use core::fmt::Arguments;
   use drivers::chario::CharIO;
+  use super::Level;
 
   /// Set the debugging output backend (mock)
   pub fn set_backend(_: &CharIO) { }
@@ -74,6 +275,23 @@ mod internal {
   /// Print debugging output backend (mock)
   pub fn print(_: &str) { }
 
+  /// Print a number in hex to the debugging output backend (mock)
+  pub fn print_hex(_: u32) { }
+
+  /// Sets the most verbose level that is logged (mock)
+  pub fn set_level(_: Level) { }
+
+  /// Sets the most verbose level that is logged for a module (mock)
+  pub fn set_filter(_: &'static str, _: Level) -> bool { true }
+
+  /// Returns true if messages at `level` from `module` are logged (mock)
+  #[inline(always)]
+  pub fn enabled(_: Level, _: &str) -> bool { false }
+
+  /// Logs a message (mock)
+  #[doc(hidden)]
+  pub fn log(_: Level, _: &str, _: Arguments) { }
+
   /// A token to ensure the life of the reference to the debugging output backend
   /// doesn't outlive the backend itself.
   #[must_use]
@@ -82,3 +300,128 @@ mod internal {
     hello: ()
   }
 }
+
+#[cfg(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+mod ring {
+  use core::cell::UnsafeCell;
+  use core::convert::AsMut;
+  use core::marker::Sync;
+  use core::option::Option::{self, Some, None};
+
+  use drivers::chario::CharIO;
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+
+  /// A debugging output backend that stores output in `A` until it is
+  /// drained.
+  ///
+  /// Writing never blocks, output that doesn't fit is dropped and counted.
+  pub struct RingBuffer<A> {
+    buffer: UnsafeCell<A>,
+    head: UnsafeCell<usize>,
+    len: UnsafeCell<usize>,
+    dropped: UnsafeCell<u32>,
+    write_hook: UnsafeCell<Option<fn()>>,
+  }
+
+  impl<A> RingBuffer<A> {
+    /// Creates a new empty ring buffer, storing output in `buffer`.
+    pub const fn new(buffer: A) -> RingBuffer<A> {
+      RingBuffer {
+        buffer: UnsafeCell::new(buffer),
+        head: UnsafeCell::new(0),
+        len: UnsafeCell::new(0),
+        dropped: UnsafeCell::new(0),
+        write_hook: UnsafeCell::new(None),
+      }
+    }
+
+    /// Sets a function called after output is written, typically to enable
+    /// the transmit interrupt that drains the buffer.
+    pub fn set_write_hook(&self, hook: fn()) {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.write_hook.get() = Some(hook) };
+    }
+  }
+
+  impl<A: AsMut<[u8]>> RingBuffer<A> {
+    /// Takes the oldest byte of output, if any.
+    ///
+    /// Safe to call from interrupt handlers.
+    pub fn pop(&self) -> Option<u8> {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        let buffer = (*self.buffer.get()).as_mut();
+        let len = *self.len.get();
+        if len == 0 {
+          return None;
+        }
+        let head = *self.head.get();
+        *self.head.get() = (head + 1) % buffer.len();
+        *self.len.get() = len - 1;
+        Some(buffer[head])
+      }
+    }
+
+    /// Writes up to `max` bytes of output to `out`, returning how many were
+    /// written.
+    ///
+    /// Meant to be called from the interrupt handler of `out`, with `max` the
+    /// space in its transmit FIFO.
+    pub fn drain_to(&self, out: &CharIO, max: usize) -> usize {
+      let mut n = 0;
+      while n < max {
+        match self.pop() {
+          Some(c) => out.putc(c as char),
+          None => break,
+        }
+        n += 1;
+      }
+      n
+    }
+
+    /// Returns true if there is no output to drain.
+    pub fn is_empty(&self) -> bool {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.len.get() == 0 }
+    }
+
+    /// Returns the number of bytes dropped because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+      let _crit = NoInterrupts::new();
+      unsafe { *self.dropped.get() }
+    }
+  }
+
+  impl<A: AsMut<[u8]>> CharIO for RingBuffer<A> {
+    fn putc(&self, value: char) {
+      let hook = {
+        let _crit = NoInterrupts::new();
+        unsafe {
+          let buffer = (*self.buffer.get()).as_mut();
+          let len = *self.len.get();
+          if len == buffer.len() {
+            *self.dropped.get() = (*self.dropped.get()).wrapping_add(1);
+            return;
+          }
+          buffer[(*self.head.get() + len) % buffer.len()] = value as u8;
+          *self.len.get() = len + 1;
+          *self.write_hook.get()
+        }
+      };
+      match hook {
+        Some(hook) => hook(),
+        None => {},
+      }
+    }
+  }
+
+  unsafe impl<A> Sync for RingBuffer<A> {}
+}
@@ -0,0 +1,169 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Deferred interrupt work.
+
+Interrupt handlers call `defer` to have a function run later, after all
+interrupts have been handled, keeping the handlers short.
+
+Without multitasking, the work runs in the PendSV handler, which `init` gives
+the lowest exception priority. The application's `isr_pendsv` must call
+`run_pending`:
+
+```ignore
+#[no_mangle]
+pub unsafe extern fn isr_pendsv() {
+  deferred::run_pending();
+}
+```
+
+With multitasking, PendSV switches tasks, so the work runs in the `worker`
+task instead, which must be added to the tasks:
+
+```ignore
+tasks! {
+  ...
+  worker(0): priority 3, stack 512;
+}
+```
+*/
+
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+
+#[cfg(not(feature = "multitasking"))]
+pub use self::backend::init;
+#[cfg(feature = "multitasking")]
+pub use self::backend::worker;
+use self::backend::notify;
+
+/// Deferred work, called with the argument given to `defer`.
+pub type Work = fn(u32);
+
+/// Number of pieces of work that can be pending.
+#[allow(non_upper_case_globals)]
+pub const QueueSize: usize = 16;
+
+#[allow(non_upper_case_globals)]
+static mut Pending: [Option<(Work, u32)>; QueueSize] = [None; QueueSize];
+#[allow(non_upper_case_globals)]
+static mut Head: usize = 0;
+#[allow(non_upper_case_globals)]
+static mut Len: usize = 0;
+
+/// Queues `work` to be called with `arg`.
+///
+/// Safe to call from interrupt handlers. Returns false if the queue is full.
+pub fn defer(work: Work, arg: u32) -> bool {
+  let crit = NoInterrupts::new();
+  unsafe {
+    if Len == QueueSize {
+      return false;
+    }
+    Pending[(Head + Len) % QueueSize] = Some((work, arg));
+    Len += 1;
+    notify(&crit);
+  }
+  true
+}
+
+/// Runs the pending work, including any queued meanwhile.
+pub fn run_pending() {
+  loop {
+    let next = {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        if Len == 0 {
+          return;
+        }
+        let next = Pending[Head];
+        Pending[Head] = None;
+        Head = (Head + 1) % QueueSize;
+        Len -= 1;
+        next
+      }
+    };
+    match next {
+      Some((work, arg)) => work(arg),
+      None => {},
+    }
+  }
+}
+
+#[cfg(not(feature = "multitasking"))]
+mod backend {
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::scb;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::scb;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::scb;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::scb;
+  use super::NoInterrupts;
+
+  /// PendSV exception number.
+  #[allow(non_upper_case_globals)]
+  const PendSV: usize = 14;
+
+  /// Gives PendSV the lowest priority, so work runs after all interrupts.
+  pub fn init() {
+    scb::set_handler_priority(PendSV, 0xff);
+  }
+
+  /// Makes PendSV run the work.
+  pub unsafe fn notify(_: &NoInterrupts) {
+    scb::set_pendsv(true);
+  }
+}
+
+#[cfg(feature = "multitasking")]
+mod backend {
+  use core::option::Option::None;
+
+  use os::task::{WaitList, wait, wake_one};
+  use util::queue::Queue;
+  use super::{Len, NoInterrupts, run_pending};
+
+  #[allow(non_upper_case_globals)]
+  static mut Idle: WaitList = Queue::new();
+
+  /// Task running deferred work, it never returns.
+  pub fn worker(_: u32) {
+    loop {
+      run_pending();
+      let crit = NoInterrupts::new();
+      unsafe {
+        if Len == 0 {
+          wait(&Idle, None, crit);
+        }
+      }
+    }
+  }
+
+  /// Wakes up the worker.
+  pub unsafe fn notify(crit: &NoInterrupts) {
+    wake_one(&Idle, crit);
+  }
+}
@@ -0,0 +1,134 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Event flag groups.
+
+A group holds 32 flags. Tasks wait for any or all of a set of flags, which are
+set from other tasks or interrupt handlers. Waiting does not clear the flags,
+use `clear` for that.
+*/
+
+use core::cell::UnsafeCell;
+use core::intrinsics::abort;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{WaitList, wait, wake_one};
+use os::time::{Duration, Instant};
+use util::queue::Queue;
+
+/// Condition to wait for.
+#[derive(Clone, Copy)]
+pub enum Wait {
+  /// Any flag of the mask is set.
+  Any,
+  /// All flags of the mask are set.
+  All,
+}
+
+/// A group of event flags.
+pub struct EventFlags {
+  flags: UnsafeCell<u32>,
+  waiting: WaitList,
+}
+
+impl EventFlags {
+  /// Creates a group with all flags cleared.
+  pub const fn new() -> EventFlags {
+    EventFlags {
+      flags: UnsafeCell::new(0),
+      waiting: Queue::new(),
+    }
+  }
+
+  /// Sets flags and wakes up waiting tasks.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn set(&self, mask: u32) {
+    let crit = NoInterrupts::new();
+    unsafe {
+      *self.flags.get() |= mask;
+      // Every waiter checks its own condition once it runs.
+      while wake_one(&self.waiting, &crit) {}
+    }
+  }
+
+  /// Clears flags.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn clear(&self, mask: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.flags.get() &= !mask };
+  }
+
+  /// Returns the current flags.
+  pub fn get(&self) -> u32 {
+    unsafe { *self.flags.get() }
+  }
+
+  /// Waits for the flags in `mask`, returning all the flags at that time.
+  pub fn wait(&self, mask: u32, condition: Wait) -> u32 {
+    match self.wait_until(mask, condition, None) {
+      Some(flags) => flags,
+      None => unsafe { abort() },
+    }
+  }
+
+  /// Waits at most `timeout` for the flags in `mask`, returning all the flags
+  /// at that time, or `None` if it timed out.
+  pub fn wait_timeout(&self, mask: u32, condition: Wait, timeout: Duration)
+      -> Option<u32> {
+    self.wait_until(mask, condition, Some(Instant::now() + timeout))
+  }
+
+  fn wait_until(&self, mask: u32, condition: Wait, deadline: Option<Instant>)
+      -> Option<u32> {
+    loop {
+      let crit = NoInterrupts::new();
+      unsafe {
+        let flags = *self.flags.get();
+        if satisfied(flags, mask, condition) {
+          return Some(flags);
+        }
+        if !wait(&self.waiting, deadline, crit) {
+          let flags = *self.flags.get();
+          return if satisfied(flags, mask, condition) {
+            Some(flags)
+          } else {
+            None
+          };
+        }
+      }
+    }
+  }
+}
+
+fn satisfied(flags: u32, mask: u32, condition: Wait) -> bool {
+  match condition {
+    Wait::Any => flags & mask != 0,
+    Wait::All => flags & mask == mask,
+  }
+}
+
+unsafe impl Sync for EventFlags {}
@@ -0,0 +1,191 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Cooperative executor for futures.
+
+A lighter alternative to `os::task`: all futures run on the main stack, and
+the core sleeps while none of them can make progress. A future that has to
+wait registers its `Waker` in a `WakerSlot`, which the interrupt handler of
+the peripheral it waits for wakes. Only woken futures are polled again.
+
+The compiler has no `async`/`await` support yet, so futures are written by
+hand as state machines implementing `Future`. The HAL provides futures for
+peripherals, e.g. `hal::lpc17xx::uart::UART::write_async`.
+
+```ignore
+struct Echo<'a> { uart: &'a UART, read: Option<ReadFuture<'a>> }
+
+impl<'a> Future for Echo<'a> {
+  type Output = ();
+
+  fn poll(&mut self, waker: &Waker) -> Poll<()> {
+    ...
+  }
+}
+
+executor::run(&mut [&mut echo, &mut blink]);
+```
+
+No memory is allocated, the futures are owned by the caller of `run`.
+*/
+
+use core::cell::UnsafeCell;
+use core::intrinsics::abort;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use util::support::wfi;
+
+/// Result of polling a future.
+pub enum Poll<T> {
+  /// The future has completed with a value.
+  Ready(T),
+  /// The future can't make progress yet, and will be woken up when it can.
+  Pending,
+}
+
+/// A computation that completes at some point.
+pub trait Future {
+  /// The value the future completes with.
+  type Output;
+
+  /// Makes as much progress as possible.
+  ///
+  /// If the future can't complete yet, it must arrange for `waker` to be
+  /// woken when it can make progress, and return `Pending`.
+  fn poll(&mut self, waker: &Waker) -> Poll<Self::Output>;
+}
+
+/// Futures woken since they were last polled, a bit per future.
+#[allow(non_upper_case_globals)]
+static mut Woken: u32 = 0;
+
+/// Maximum number of futures `run` can drive.
+#[allow(non_upper_case_globals)]
+pub const MaxFutures: usize = 32;
+
+/// Wakes up a future of the executor.
+#[derive(Clone, Copy)]
+pub struct Waker {
+  bit: u32,
+}
+
+impl Waker {
+  /// Makes the executor poll the future again.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn wake(&self) {
+    let _crit = NoInterrupts::new();
+    unsafe { Woken |= self.bit };
+  }
+}
+
+/// Holds the waker of a future waiting for an interrupt.
+///
+/// Usually a static per peripheral, the future registers its waker before
+/// enabling the interrupt, and the interrupt handler wakes it.
+pub struct WakerSlot {
+  waker: UnsafeCell<Option<Waker>>,
+}
+
+impl WakerSlot {
+  /// Creates an empty slot.
+  pub const fn new() -> WakerSlot {
+    WakerSlot { waker: UnsafeCell::new(None) }
+  }
+
+  /// Registers the waker to wake, replacing any previous one.
+  pub fn register(&self, waker: &Waker) {
+    let _crit = NoInterrupts::new();
+    unsafe { *self.waker.get() = Some(*waker) };
+  }
+
+  /// Wakes the registered waker, if any, and clears the slot.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn wake(&self) {
+    let waker = {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        let waker = *self.waker.get();
+        *self.waker.get() = None;
+        waker
+      }
+    };
+    match waker {
+      Some(waker) => waker.wake(),
+      None => {},
+    }
+  }
+}
+
+unsafe impl Sync for WakerSlot {}
+
+/// Runs the futures until all of them have completed.
+///
+/// All futures are polled once, afterwards only when woken. The core sleeps
+/// in between.
+pub fn run(futures: &mut [&mut Future<Output=()>]) {
+  if futures.len() > MaxFutures {
+    unsafe { abort() };
+  }
+
+  let all = if futures.len() == MaxFutures {
+    !0
+  } else {
+    (1u32 << futures.len()) - 1
+  };
+  let mut pending = all;
+  unsafe {
+    let _crit = NoInterrupts::new();
+    Woken |= all;
+  }
+
+  while pending != 0 {
+    let woken = {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        let woken = Woken & pending;
+        Woken &= !woken;
+        if woken == 0 {
+          // Returns on a pending interrupt even though they are masked, the
+          // handler runs once the critical section ends.
+          wfi();
+        }
+        woken
+      }
+    };
+
+    for (i, future) in futures.iter_mut().enumerate() {
+      let bit = 1 << i;
+      if woken & bit == 0 {
+        continue;
+      }
+      match future.poll(&Waker { bit: bit }) {
+        Poll::Ready(()) => pending &= !bit,
+        Poll::Pending => {},
+      }
+    }
+  }
+}
@@ -0,0 +1,180 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Bounded message queues.
+
+A queue stores its messages in a fixed size array given at construction, so it
+can be allocated statically:
+
+```ignore
+static RX: MessageQueue<u8, [u8; 16]> = MessageQueue::new([0; 16]);
+```
+
+The `try_` variants never block and can be used from interrupt handlers. The
+blocking variants must only be called from tasks.
+*/
+
+use core::cell::UnsafeCell;
+use core::convert::AsMut;
+use core::intrinsics::abort;
+use core::marker::{Copy, PhantomData, Send, Sync};
+use core::option::Option::{self, Some, None};
+use core::result::Result::{self, Ok, Err};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{WaitList, wait, wake_one};
+use os::time::{Duration, Instant};
+use util::queue::Queue;
+
+/// A bounded queue of messages of type `T`, stored in `A`.
+pub struct MessageQueue<T, A> {
+  buffer: UnsafeCell<A>,
+  head: UnsafeCell<usize>,
+  len: UnsafeCell<usize>,
+  receivers: WaitList,
+  senders: WaitList,
+  marker: PhantomData<T>,
+}
+
+/// A queue that holds a single message.
+pub type Mailbox<T> = MessageQueue<T, [T; 1]>;
+
+impl<T, A> MessageQueue<T, A> {
+  /// Creates a new empty queue, using `buffer` to store messages.
+  ///
+  /// The initial contents of the buffer are ignored.
+  pub const fn new(buffer: A) -> MessageQueue<T, A> {
+    MessageQueue {
+      buffer: UnsafeCell::new(buffer),
+      head: UnsafeCell::new(0),
+      len: UnsafeCell::new(0),
+      receivers: Queue::new(),
+      senders: Queue::new(),
+      marker: PhantomData,
+    }
+  }
+}
+
+impl<T: Copy, A: AsMut<[T]>> MessageQueue<T, A> {
+  /// Sends a message if there is space, returning it back otherwise.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn try_send(&self, msg: T) -> Result<(), T> {
+    let crit = NoInterrupts::new();
+    unsafe { self.put(msg, &crit) }
+  }
+
+  /// Sends a message, waiting for space in the queue.
+  pub fn send(&self, msg: T) {
+    let _ = self.send_until(msg, None);
+  }
+
+  /// Sends a message, waiting at most `timeout` for space in the queue.
+  ///
+  /// Returns the message back if it timed out.
+  pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), T> {
+    self.send_until(msg, Some(Instant::now() + timeout))
+  }
+
+  /// Receives a message if there is one.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn try_receive(&self) -> Option<T> {
+    let crit = NoInterrupts::new();
+    unsafe { self.take(&crit) }
+  }
+
+  /// Receives a message, waiting for one to arrive.
+  pub fn receive(&self) -> T {
+    match self.receive_until(None) {
+      Some(msg) => msg,
+      None => unsafe { abort() },
+    }
+  }
+
+  /// Receives a message, waiting at most `timeout` for one to arrive.
+  pub fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+    self.receive_until(Some(Instant::now() + timeout))
+  }
+
+  fn send_until(&self, msg: T, deadline: Option<Instant>) -> Result<(), T> {
+    loop {
+      let crit = NoInterrupts::new();
+      unsafe {
+        if self.put(msg, &crit).is_ok() {
+          return Ok(());
+        }
+        if !wait(&self.senders, deadline, crit) {
+          let crit = NoInterrupts::new();
+          return self.put(msg, &crit);
+        }
+      }
+    }
+  }
+
+  fn receive_until(&self, deadline: Option<Instant>) -> Option<T> {
+    loop {
+      let crit = NoInterrupts::new();
+      unsafe {
+        match self.take(&crit) {
+          Some(msg) => return Some(msg),
+          None => {},
+        }
+        if !wait(&self.receivers, deadline, crit) {
+          let crit = NoInterrupts::new();
+          return self.take(&crit);
+        }
+      }
+    }
+  }
+
+  unsafe fn put(&self, msg: T, crit: &NoInterrupts) -> Result<(), T> {
+    let buffer = (*self.buffer.get()).as_mut();
+    let len = *self.len.get();
+    if len == buffer.len() {
+      return Err(msg);
+    }
+
+    buffer[(*self.head.get() + len) % buffer.len()] = msg;
+    *self.len.get() = len + 1;
+    wake_one(&self.receivers, crit);
+    Ok(())
+  }
+
+  unsafe fn take(&self, crit: &NoInterrupts) -> Option<T> {
+    let buffer = (*self.buffer.get()).as_mut();
+    let len = *self.len.get();
+    if len == 0 {
+      return None;
+    }
+
+    let head = *self.head.get();
+    let msg = buffer[head];
+    *self.head.get() = (head + 1) % buffer.len();
+    *self.len.get() = len - 1;
+    wake_one(&self.senders, crit);
+    Some(msg)
+  }
+}
+
+unsafe impl<T: Send, A> Sync for MessageQueue<T, A> {}
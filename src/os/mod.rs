@@ -21,8 +21,19 @@ incompatible direct hal usage in some cases.
 */
 
 // pub mod debug;
+#[cfg(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub mod executor;
+#[cfg(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+          feature = "cpu_cortex-m4", feature = "cpu_cortex-m7"))]
+pub mod deferred;
 pub mod syscall;
 #[cfg(feature = "multitasking")] pub mod task;
+pub mod time;
 pub mod mutex;
 pub mod cond_var;
+#[cfg(feature = "multitasking")] pub mod message_queue;
+#[cfg(feature = "multitasking")] pub mod semaphore;
+#[cfg(feature = "multitasking")] pub mod event_flags;
+#[cfg(feature = "multitasking")] pub mod trace;
 pub mod debug;
@@ -17,31 +17,45 @@
 
 pub use os::mutex::internal::{MUTEX_INIT, Mutex, Guard};
 
-#[cfg(multitasking)]
+#[cfg(feature = "multitasking")]
 mod internal {
-  use core::marker;
-  use core::ty::Unsafe;
-  use core::kinds::Sync;
-  use core::option::Option::{None, Some};
+  use core::cell::UnsafeCell;
+  use core::intrinsics::abort;
+  use core::marker::Sync;
+  use core::option::Option::{self, None, Some};
   use core::ops::Drop;
 
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
   use hal::cortex_m3::irq::NoInterrupts;
-  use os::task::{TaskDescriptor, Tasks};
-  use util::queue::{Queue, Node};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+  use os::task::{Priority, TaskDescriptor, Tasks, WaitList, highest_waiter,
+                 wait};
+  use os::time::{Duration, Instant};
+  use os::trace;
+  use util::queue::Queue;
 
   /// A mutex
+  ///
+  /// While a task waits for the mutex, its owner inherits the waiter's
+  /// priority if it is higher, so that tasks of intermediate priority can't
+  /// delay the waiter indefinitely.
   pub struct Mutex {
-    owner: UnsafeCell<Option<*mut TaskDescriptor>>,
-    waiting: Queue<*mut TaskDescriptor>
+    owner: UnsafeCell<*mut TaskDescriptor>,
+    waiting: WaitList,
+    /// Next mutex held by the same owner.
+    next_held: UnsafeCell<*const Mutex>,
   }
 
   /// Static initializer
   pub const MUTEX_INIT: Mutex = Mutex {
-    owner: UnsafeCell::new(None),
-    waiting: Queue {
-      head: UnsafeCell::new(0 as *mut Node<*mut TaskDescriptor>),
-      tail: UnsafeCell::new(0 as *mut Node<*mut TaskDescriptor>),
-    }
+    owner: UnsafeCell::new(0 as *mut TaskDescriptor),
+    waiting: Queue::new(),
+    next_held: UnsafeCell::new(0 as *const Mutex),
   };
 
   /// A mutex lock
@@ -53,82 +67,170 @@ mod internal {
   impl Mutex {
     /// Create a new mutex
     pub fn new() -> Mutex {
-      Mutex {
-        owner: UnsafeCell::new(None),
-        waiting: Queue::new(),
-      }
+      MUTEX_INIT
     }
 
     /// Take a mutex lock
     pub fn lock<'a>(&'a self) -> Guard<'a> {
+      match self.lock_until(None) {
+        Some(guard) => guard,
+        None => unsafe { abort() },
+      }
+    }
+
+    /// Take a mutex lock, waiting at most `timeout` for it. Returns `None`
+    /// if it timed out.
+    pub fn lock_timeout<'a>(&'a self, timeout: Duration)
+        -> Option<Guard<'a>> {
+      self.lock_until(Some(Instant::now() + timeout))
+    }
+
+    fn lock_until<'a>(&'a self, deadline: Option<Instant>)
+        -> Option<Guard<'a>> {
       /*
-       * This is a bit subtle: We need to add ourselves to the mutex's
-       * waiting list. To do this we allocate a list item on the local
-       * stack, append it to the waiting list, and block. When the task
-       * before us unlocks the mutex, they will wake us up. Finally, when
-       * we are executing again we remove our entry from the list.
+       * If the mutex is taken, we put ourselves on its waiting list and
+       * block, lending our priority to the owner. When the owner unlocks the
+       * mutex, it hands ownership directly to the highest priority waiter,
+       * so there is no race with other tasks claiming it first.
        */
       unsafe {
-        // we need the critical section until the end of this function
-        let _crit = match *self.owner.get() {
-          None    => NoInterrupts::new(),
-          Some(_) => {
-            let crit = NoInterrupts::new();
-            let mut waiting = Node::new(Tasks.current_task() as *mut TaskDescriptor);
-            self.waiting.push(&mut waiting, &crit);
-            Tasks.current_task().block(crit); // drops crit
-
-            /*
-             * Note that there is a small window here between being
-             * awoken by the unlocking thread and reentering a critical
-             * section. An interrupt could fire within this window but
-             * since the unlocking thread retains ownership of the mutex
-             * there is no risk of a third-party sneaking in.
-             */
-            let crit = NoInterrupts::new();
-            self.waiting.pop(&crit);
-            crit
-          }
-        };
+        let crit = NoInterrupts::new();
+        let current = Tasks.current_task();
+        if (*self.owner.get()).is_null() {
+          self.acquire(current, &crit);
+          return Some(Guard { mutex: self });
+        }
+
+        current.blocked_on = self;
+        inherit(self, current.priority, &crit);
+        trace::record(trace::Kind::MutexBlock, self as *const Mutex as u32);
+        wait(&self.waiting, deadline, crit);
 
-        *self.owner.get() = Some(Tasks.current_task() as *mut TaskDescriptor);
-        Guard { mutex: self }
+        // The mutex may have been handed over to us between the timeout and
+        // the task running again, in which case we keep it.
+        let crit = NoInterrupts::new();
+        if *self.owner.get() == &mut *current as *mut TaskDescriptor {
+          return Some(Guard { mutex: self });
+        }
+        current.blocked_on = 0 as *const Mutex;
+        disinherit(self, &crit);
+        None
       }
     }
 
     /// Try to take a mutex lock, returning `None` is unsuccessful
     pub fn try_lock<'a>(&'a self) -> Option<Guard<'a>> {
       unsafe {
-        match *self.owner.get() {
-          None => {
-            let _crit = NoInterrupts::new();
-            *self.owner.get() = Some(Tasks.current_task() as *mut TaskDescriptor);
-            Some(Guard { mutex: self })
-          }
-          _ => None
+        let crit = NoInterrupts::new();
+        if (*self.owner.get()).is_null() {
+          self.acquire(Tasks.current_task(), &crit);
+          Some(Guard { mutex: self })
+        } else {
+          None
         }
       }
     }
 
-    /*
-     * Here we release ownership of the mutex only if there is no one
-     * waiting on it. Otherwise we retain to ensure there is no race
-     * between waking up the waiting thread and it claiming ownership.
-     */
-    fn unlock(&self) {
+    /// Makes `task` the owner of the mutex.
+    unsafe fn acquire(&self, task: &mut TaskDescriptor, _: &NoInterrupts) {
+      *self.owner.get() = &mut *task;
+      *self.next_held.get() = task.held_mutexes;
+      task.held_mutexes = self;
+    }
+
+    /// Hands the mutex over to the highest priority waiter, if any, and
+    /// restores the priority of the previous owner.
+    ///
+    /// Guards unlock the mutex when dropped, this is for locks whose guard
+    /// was forgotten. Returns false if the current task doesn't own the
+    /// mutex.
+    pub fn unlock(&self) -> bool {
       unsafe {
         let crit = NoInterrupts::new();
-        match self.waiting.peek() {
-          None => *self.owner.get() = None,
-          Some(nextTask) => {
-            let mut task = *(*nextTask).data;
-            task.unblock(&crit);
+        if *self.owner.get() != &mut *Tasks.current_task() as *mut _ {
+          return false;
+        }
+        let owner = &mut **self.owner.get();
+        self.release(owner);
+        let priority = owned_priority(owner);
+        owner.set_priority(priority, &crit);
+
+        match highest_waiter(&self.waiting) {
+          None => *self.owner.get() = 0 as *mut TaskDescriptor,
+          Some(node) => {
+            self.waiting.remove(node, &crit);
+            let next = &mut *(*node).data;
+            next.blocked_on = 0 as *const Mutex;
+            self.acquire(next, &crit);
+            let priority = owned_priority(next);
+            next.set_priority(priority, &crit);
+            next.unblock(&crit);
           }
         }
+        true
+      }
+    }
+
+    /// Removes the mutex from the list of mutexes held by `owner`.
+    unsafe fn release(&self, owner: &mut TaskDescriptor) {
+      let mut link: *mut *const Mutex = &mut owner.held_mutexes;
+      while !(*link).is_null() {
+        if *link == self as *const Mutex {
+          *link = *self.next_held.get();
+          break;
+        }
+        link = (**link).next_held.get();
+      }
+      *self.next_held.get() = 0 as *const Mutex;
+    }
+  }
+
+  /// Raises the priority of the owner of `mutex` to `priority`.
+  ///
+  /// If the owner itself waits for another mutex, the priority is passed on
+  /// along the chain.
+  unsafe fn inherit(mutex: &Mutex, priority: Priority, crit: &NoInterrupts) {
+    let mut mutex = mutex as *const Mutex;
+    while !mutex.is_null() {
+      let owner = &mut **(*mutex).owner.get();
+      if owner.priority >= priority {
+        break;
+      }
+      owner.set_priority(priority, crit);
+      mutex = owner.blocked_on;
+    }
+  }
+
+  /// Lowers the priority of the owner of `mutex` back after a waiter gave up,
+  /// passing the change on along the chain like `inherit`.
+  unsafe fn disinherit(mutex: &Mutex, crit: &NoInterrupts) {
+    let mut mutex = mutex as *const Mutex;
+    while !mutex.is_null() && !(*(*mutex).owner.get()).is_null() {
+      let owner = &mut **(*mutex).owner.get();
+      let priority = owned_priority(owner);
+      if owner.priority == priority {
+        break;
       }
+      owner.set_priority(priority, crit);
+      mutex = owner.blocked_on;
     }
   }
 
+  /// Returns the priority `task` should run with, given the mutexes it holds.
+  unsafe fn owned_priority(task: &TaskDescriptor) -> Priority {
+    let mut priority = task.base_priority;
+    let mut mutex = task.held_mutexes;
+    while !mutex.is_null() {
+      match highest_waiter(&(*mutex).waiting) {
+        Some(node) if (*(*node).data).priority > priority =>
+          priority = (*(*node).data).priority,
+        _ => {},
+      }
+      mutex = *(*mutex).next_held.get();
+    }
+    priority
+  }
+
   impl<'a> Drop for Guard<'a> {
     #[inline]
     fn drop(&mut self) {
@@ -136,10 +238,10 @@ mod internal {
     }
   }
 
-  impl Sync for Mutex { }
+  unsafe impl Sync for Mutex { }
 }
 
-#[cfg(not(multitasking))]
+#[cfg(not(feature = "multitasking"))]
 mod internal {
   use core::marker::Sync;
   use core::option::Option::{self, None, Some};
@@ -147,6 +249,8 @@ mod internal {
   use core::intrinsics::abort;
   use core::cell::UnsafeCell;
 
+  use os::time::Duration;
+
   /// A mutex
   pub struct Mutex {
     taken: UnsafeCell<bool>,
@@ -190,6 +294,15 @@ mod internal {
         }
       }
     }
+
+    /// Take a mutex lock, waiting at most `timeout` for it. Returns `None`
+    /// if it timed out.
+    ///
+    /// Without tasks, nothing can unlock the mutex meanwhile, so this is
+    /// `try_lock`.
+    pub fn lock_timeout<'a>(&'a self, _: Duration) -> Option<Guard<'a>> {
+      self.try_lock()
+    }
   }
 
   impl<'a> Drop for Guard<'a> {
@@ -0,0 +1,125 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Counting semaphores.
+
+`give` and `try_take` never block and can be used from interrupt handlers. A
+semaphore with `max` of 1 is a binary semaphore.
+*/
+
+use core::cell::UnsafeCell;
+use core::marker::Sync;
+use core::option::Option::{self, Some, None};
+
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+use os::task::{WaitList, wait, wake_one};
+use os::time::{Duration, Instant};
+use util::queue::Queue;
+
+/// A counting semaphore.
+pub struct Semaphore {
+  count: UnsafeCell<u32>,
+  max: u32,
+  waiting: WaitList,
+}
+
+impl Semaphore {
+  /// Creates a semaphore with `initial` units available, holding at most
+  /// `max` units.
+  pub const fn new(initial: u32, max: u32) -> Semaphore {
+    Semaphore {
+      count: UnsafeCell::new(initial),
+      max: max,
+      waiting: Queue::new(),
+    }
+  }
+
+  /// Returns a unit to the semaphore, waking up a waiting task.
+  ///
+  /// Returns false if the semaphore already holds `max` units. Safe to call
+  /// from interrupt handlers.
+  pub fn give(&self) -> bool {
+    let crit = NoInterrupts::new();
+    unsafe {
+      let count = self.count.get();
+      if *count == self.max {
+        return false;
+      }
+      *count += 1;
+      wake_one(&self.waiting, &crit);
+    }
+    true
+  }
+
+  /// Takes a unit if one is available.
+  ///
+  /// Safe to call from interrupt handlers.
+  pub fn try_take(&self) -> bool {
+    let crit = NoInterrupts::new();
+    unsafe { self.acquire(&crit) }
+  }
+
+  /// Takes a unit, waiting for one to become available.
+  pub fn take(&self) {
+    self.take_until(None);
+  }
+
+  /// Takes a unit, waiting at most `timeout` for one to become available.
+  ///
+  /// Returns false if it timed out.
+  pub fn take_timeout(&self, timeout: Duration) -> bool {
+    self.take_until(Some(Instant::now() + timeout))
+  }
+
+  /// Returns the number of available units.
+  pub fn count(&self) -> u32 {
+    unsafe { *self.count.get() }
+  }
+
+  fn take_until(&self, deadline: Option<Instant>) -> bool {
+    loop {
+      let crit = NoInterrupts::new();
+      unsafe {
+        if self.acquire(&crit) {
+          return true;
+        }
+        if !wait(&self.waiting, deadline, crit) {
+          let crit = NoInterrupts::new();
+          return self.acquire(&crit);
+        }
+      }
+    }
+  }
+
+  unsafe fn acquire(&self, _: &NoInterrupts) -> bool {
+    let count = self.count.get();
+    if *count == 0 {
+      false
+    } else {
+      *count -= 1;
+      true
+    }
+  }
+}
+
+unsafe impl Sync for Semaphore {}
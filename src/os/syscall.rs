@@ -16,10 +16,439 @@
 /*!
 Syscall interface.
 
-This module provides syscall interface that is implemented in assembly due to
-current rust restrictions (see hal/cortex_m3/sched.S for actual implementation).
+The entry points are implemented in assembly due to current rust restrictions
+(see hal/cortex_m3/sched.S and hal/cortex_m0/sched.S for actual
+implementation), they pass the exception frame to `svc_handler`, which
+dispatches on the SVC number.
+
+Kernel services are requested with SVC 3, giving the service number in r3 and
+up to three arguments in r0-r2. A service runs in thread mode on the stack of
+the calling task, privileged, so it may block. The typed functions of this
+module wrap the services, and can be used by isolated tasks.
+
+Mutexes and message queues are passed to services as handles, indices into
+tables kept by the kernel, so tasks can only use the objects registered with
+`register_mutex` and `register_queue`. Message pointers are checked against
+the memory the calling task may access.
 */
 
 extern {
+  /// Calls `f` with `arg` in the SVCall handler.
+  ///
+  /// Only privileged code may use this, unprivileged callers are killed.
   pub fn syscall(f: fn(u32), arg: u32);
 }
+
+#[cfg(feature = "multitasking")]
+pub use self::services::*;
+
+#[cfg(feature = "multitasking")]
+mod services {
+  use core::convert::AsMut;
+  use core::marker::{Copy, PhantomData};
+  use core::mem::{forget, size_of, transmute, uninitialized};
+  use core::ops::Drop;
+  use core::option::Option::{self, Some, None};
+
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::sched;
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::{self, NoInterrupts};
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::ExceptionFrame;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::sched;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::irq::{self, NoInterrupts};
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::fault::ExceptionFrame;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::sched;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::{self, NoInterrupts};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::fault::ExceptionFrame;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::sched;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::{self, NoInterrupts};
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::fault::ExceptionFrame;
+  use os::message_queue::MessageQueue;
+  use os::mutex::Mutex;
+  use os::task::{self, Tasks, kill_current_task};
+  use os::time::Duration;
+
+  /// SVC numbers.
+  ///
+  /// `EnterCritical` and `LeaveCritical` are issued by
+  /// `hal::cortex_common::irq`.
+  #[allow(non_upper_case_globals)]
+  mod svc {
+    /// Calls a function in the SVCall handler.
+    pub const Call: u8 = 0;
+    /// Enters a critical section of an unprivileged task.
+    pub const EnterCritical: u8 = 1;
+    /// Returns from a service to the task.
+    pub const ServiceReturn: u8 = 2;
+    /// Runs a service.
+    pub const Service: u8 = 3;
+    /// Reports a stack overflow of the current task.
+    pub const StackOverflow: u8 = 4;
+    /// Leaves a critical section of an unprivileged task.
+    pub const LeaveCritical: u8 = 5;
+    /// Makes a panicking task privileged and masks interrupts.
+    pub const Panic: u8 = 6;
+  }
+
+  /// Kernel services, see the functions wrapping them.
+  #[derive(Clone, Copy)]
+  #[allow(missing_docs)]
+  pub enum Service {
+    Yield,
+    Sleep,
+    TaskId,
+    MutexLock,
+    MutexUnlock,
+    QueueSend,
+    QueueReceive,
+  }
+
+  type ServiceFn = fn(u32, u32, u32) -> u32;
+
+  /// Services, indexed by `Service`.
+  #[allow(non_upper_case_globals)]
+  static Services: [ServiceFn; 7] = [
+    yield_service,
+    sleep_service,
+    task_id_service,
+    mutex_lock_service,
+    mutex_unlock_service,
+    queue_send_service,
+    queue_receive_service,
+  ];
+
+  extern {
+    fn service_call(a: u32, b: u32, c: u32, service: u32) -> u32;
+    fn service_return();
+  }
+
+  /// Queues that can be used through services, with the message type erased.
+  trait Channel {
+    /// Size of a message in bytes.
+    fn message_size(&self) -> usize;
+    /// Sends the message at `msg`, waiting for space.
+    unsafe fn send_from(&self, msg: *const u8);
+    /// Receives a message into `msg`, waiting for one to arrive.
+    unsafe fn receive_into(&self, msg: *mut u8);
+  }
+
+  impl<T: Copy, A: AsMut<[T]>> Channel for MessageQueue<T, A> {
+    fn message_size(&self) -> usize {
+      size_of::<T>()
+    }
+
+    unsafe fn send_from(&self, msg: *const u8) {
+      self.send(*(msg as *const T));
+    }
+
+    unsafe fn receive_into(&self, msg: *mut u8) {
+      *(msg as *mut T) = self.receive();
+    }
+  }
+
+  /// Number of mutexes, and of queues, that can be registered.
+  #[allow(non_upper_case_globals)]
+  pub const MaxHandles: usize = 8;
+
+  #[allow(non_upper_case_globals)]
+  static mut Mutexes: [Option<&'static Mutex>; MaxHandles] = [None; MaxHandles];
+  #[allow(non_upper_case_globals)]
+  static mut MutexCount: usize = 0;
+  #[allow(non_upper_case_globals)]
+  static mut Queues: [Option<&'static Channel>; MaxHandles] =
+      [None; MaxHandles];
+  #[allow(non_upper_case_globals)]
+  static mut QueueCount: usize = 0;
+
+  /// A mutex registered with `register_mutex`.
+  #[derive(Clone, Copy)]
+  pub struct MutexHandle {
+    index: u32,
+  }
+
+  /// A message queue registered with `register_queue`.
+  #[derive(Clone, Copy)]
+  pub struct QueueHandle<T> {
+    index: u32,
+    message: PhantomData<T>,
+  }
+
+  /// Makes a mutex available to services.
+  ///
+  /// The kernel tables can't be written by isolated tasks, so this is done by
+  /// privileged code, usually before the scheduler starts. Returns `None` if
+  /// `MaxHandles` mutexes are registered already.
+  pub fn register_mutex(mutex: &'static Mutex) -> Option<MutexHandle> {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if MutexCount == MaxHandles {
+        return None;
+      }
+      Mutexes[MutexCount] = Some(mutex);
+      MutexCount += 1;
+      Some(MutexHandle { index: (MutexCount - 1) as u32 })
+    }
+  }
+
+  /// Makes a message queue available to services, like `register_mutex`.
+  pub fn register_queue<T: Copy + 'static, A: AsMut<[T]> + 'static>(
+      queue: &'static MessageQueue<T, A>) -> Option<QueueHandle<T>> {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if QueueCount == MaxHandles {
+        return None;
+      }
+      Queues[QueueCount] = Some(queue);
+      QueueCount += 1;
+      Some(QueueHandle {
+        index: (QueueCount - 1) as u32,
+        message: PhantomData,
+      })
+    }
+  }
+
+  /// Returns the registered mutex with the given handle.
+  unsafe fn registered_mutex(handle: u32) -> Option<&'static Mutex> {
+    if handle as usize >= MutexCount {
+      return None;
+    }
+    Mutexes[handle as usize]
+  }
+
+  /// Returns the registered queue with the given handle, if its messages are
+  /// `size` bytes long.
+  unsafe fn registered_queue(handle: u32, size: u32)
+      -> Option<&'static Channel> {
+    if handle as usize >= QueueCount {
+      return None;
+    }
+    match Queues[handle as usize] {
+      Some(queue) if queue.message_size() == size as usize => Some(queue),
+      _ => None,
+    }
+  }
+
+  /// A mutex lock taken through a service, unlocked through one when dropped.
+  #[must_use]
+  pub struct MutexGuard {
+    mutex: MutexHandle,
+  }
+
+  impl Drop for MutexGuard {
+    fn drop(&mut self) {
+      call(Service::MutexUnlock, self.mutex.index, 0, 0);
+    }
+  }
+
+  fn call(service: Service, a: u32, b: u32, c: u32) -> u32 {
+    unsafe { service_call(a, b, c, service as u32) }
+  }
+
+  /// Lets other tasks of the same priority run.
+  pub fn yield_now() {
+    call(Service::Yield, 0, 0, 0);
+  }
+
+  /// Puts the current task to sleep for the given duration.
+  pub fn sleep(duration: Duration) {
+    call(Service::Sleep, duration.ticks(), 0, 0);
+  }
+
+  /// Returns the index of the current task, in the order the tasks were
+  /// declared in.
+  pub fn task_id() -> usize {
+    call(Service::TaskId, 0, 0, 0) as usize
+  }
+
+  /// Takes a mutex lock.
+  pub fn mutex_lock(mutex: MutexHandle) -> MutexGuard {
+    call(Service::MutexLock, mutex.index, 0, 0);
+    MutexGuard { mutex: mutex }
+  }
+
+  /// Sends a message, waiting for space in the queue.
+  pub fn queue_send<T: Copy>(queue: QueueHandle<T>, msg: T) {
+    call(Service::QueueSend, queue.index, &msg as *const T as u32,
+        size_of::<T>() as u32);
+  }
+
+  /// Receives a message, waiting for one to arrive.
+  pub fn queue_receive<T: Copy>(queue: QueueHandle<T>) -> T {
+    let mut msg: T = unsafe { uninitialized() };
+    call(Service::QueueReceive, queue.index, &mut msg as *mut T as u32,
+        size_of::<T>() as u32);
+    msg
+  }
+
+  fn yield_service(_: u32, _: u32, _: u32) -> u32 {
+    sched::switch_context();
+    0
+  }
+
+  fn sleep_service(ticks: u32, _: u32, _: u32) -> u32 {
+    task::sleep(Duration::from_ticks(ticks));
+    0
+  }
+
+  fn task_id_service(_: u32, _: u32, _: u32) -> u32 {
+    unsafe { Tasks.current_task as u32 }
+  }
+
+  fn mutex_lock_service(handle: u32, _: u32, _: u32) -> u32 {
+    match unsafe { registered_mutex(handle) } {
+      Some(mutex) => forget(mutex.lock()),
+      None => kill_current_task(0),
+    }
+    0
+  }
+
+  fn mutex_unlock_service(handle: u32, _: u32, _: u32) -> u32 {
+    match unsafe { registered_mutex(handle) } {
+      Some(mutex) if mutex.unlock() => {},
+      _ => kill_current_task(0),
+    }
+    0
+  }
+
+  fn queue_send_service(handle: u32, msg: u32, size: u32) -> u32 {
+    unsafe {
+      match registered_queue(handle, size) {
+        Some(queue) if Tasks.current_task().can_access(msg, size, false) =>
+          queue.send_from(msg as *const u8),
+        _ => kill_current_task(0),
+      }
+    }
+    0
+  }
+
+  fn queue_receive_service(handle: u32, msg: u32, size: u32) -> u32 {
+    unsafe {
+      match registered_queue(handle, size) {
+        Some(queue) if Tasks.current_task().can_access(msg, size, true) =>
+          queue.receive_into(msg as *mut u8),
+        _ => kill_current_task(0),
+      }
+    }
+    0
+  }
+
+  /// Dispatches a system call, called from the SVCall handler.
+  ///
+  /// A service is started by making the exception return to the service
+  /// function in privileged thread mode, with the return address set to
+  /// `service_return`. That issues `ServiceReturn`, which returns to the
+  /// caller of `service_call` with the result in r0, at the privilege level
+  /// of the task.
+  #[no_mangle]
+  pub unsafe extern fn svc_handler(frame: &mut ExceptionFrame) {
+    let number = *((frame.pc - 2) as *const u8);
+    match number {
+      svc::Call => {
+        if sched::is_privileged() {
+          let f: fn(u32) = transmute(frame.r0);
+          f(frame.r1);
+        } else {
+          kill_current_task(0);
+        }
+      },
+      svc::EnterCritical => {
+        let task = Tasks.current_task();
+        if task.privileged() || !task.enter_critical() {
+          kill_current_task(0);
+        }
+      },
+      svc::LeaveCritical => {
+        let task = Tasks.current_task();
+        if task.privileged() || !task.leave_critical() {
+          kill_current_task(0);
+        }
+      },
+      svc::ServiceReturn => {
+        let task = Tasks.current_task();
+        if task.syscall_return == 0 {
+          kill_current_task(0);
+          return;
+        }
+        frame.pc = task.syscall_return;
+        task.syscall_return = 0;
+        sched::set_privileged(task.privileged());
+      },
+      svc::Service => {
+        let task = Tasks.current_task();
+        // Services may block, which a task in a critical section must not.
+        if frame.r3 as usize >= Services.len() || task.syscall_return != 0 ||
+            task.in_critical() {
+          kill_current_task(0);
+          return;
+        }
+        // service_call is a leaf function, so return straight to its caller.
+        task.syscall_return = frame.lr & !1;
+        frame.pc = Services[frame.r3 as usize] as u32 & !1;
+        frame.lr = service_return as u32;
+        sched::set_privileged(true);
+      },
+      svc::Panic => {
+        // Interrupts stay masked after the exception returns, so there are no
+        // context switches to take the privilege away again.
+        sched::set_privileged(true);
+        disable_irqs();
+      },
+      svc::StackOverflow => task::stack_overflow(),
+      _ => kill_current_task(0),
+    }
+  }
+
+  /// Reports a stack overflow of the current task, which is killed.
+  #[doc(hidden)]
+  #[cfg(target_arch = "arm")]
+  #[inline(always)]
+  pub fn report_stack_overflow() {
+    unsafe { asm!("svc 4" :::: "volatile") };
+  }
+
+  #[doc(hidden)]
+  #[cfg(not(target_arch = "arm"))]
+  pub fn report_stack_overflow() { unimplemented!() }
+
+  /// Masks interrupts to report a panic.
+  ///
+  /// An isolated task can neither mask interrupts nor write the record of
+  /// `util::panic`, so the kernel makes it privileged. It stays so, as the
+  /// system stops after a panic.
+  #[doc(hidden)]
+  #[cfg(target_arch = "arm")]
+  pub fn disable_irqs_for_panic() {
+    unsafe {
+      if irq::unprivileged_thread() {
+        asm!("svc 6" :::: "volatile");
+      } else {
+        disable_irqs();
+      }
+    }
+  }
+
+  #[doc(hidden)]
+  #[cfg(not(target_arch = "arm"))]
+  pub fn disable_irqs_for_panic() {}
+
+  /// Masks interrupts, they stay masked after an exception returns.
+  #[cfg(target_arch = "arm")]
+  #[inline(always)]
+  unsafe fn disable_irqs() {
+    asm!("cpsid i" :::: "volatile");
+  }
+
+  #[cfg(not(target_arch = "arm"))]
+  unsafe fn disable_irqs() { unimplemented!() }
+}
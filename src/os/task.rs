@@ -14,55 +14,116 @@
 // limitations under the License.
 
 //! Basic multitasking interface.
+//!
+//! Tasks are declared with the `tasks!` macro, which allocates their stacks at
+//! compile time and starts the scheduler.
+//!
+//! On Cortex-M4 and Cortex-M7, tasks may use the FPU, its registers are saved
+//! on context switches only for the tasks that do.
 
 use core::mem::size_of;
 use core::intrinsics::abort;
+use core::iter::Iterator;
+use core::option::Option::{self, Some, None};
 
-use hal::cortex_m3::{sched, systick};
-use hal::cortex_m3::sched::NoInterrupts;
-use os::syscall::syscall;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::{sched, scb, systick};
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::{sched, scb, systick};
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::fault::{ExceptionFrame, fault_handler};
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::{sched, scb, systick};
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::fault::{ExceptionFrame, fault_handler};
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::{sched, scb, systick};
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::fault::{ExceptionFrame, fault_handler};
+use os::debug;
+use os::mutex::Mutex;
+use os::syscall::report_stack_overflow;
+use os::time::{self, Duration, Instant};
+use os::trace;
 use hal::stack;
+use util::queue::{Queue, Node};
+use util::support::wfi;
+
+use self::Status::{Runnable, Blocked};
 
 /// Task takes one argument, which is u32.
 pub type Task = fn(u32);
 
-mod current_stack_offset {
-  /// Currently allocated stack memory, growing down, starting at __STACK_BASE.
-  static mut CurrentStackOffset: u32 = 0;
-
-  pub fn get() -> u32 {
-    unsafe { CurrentStackOffset }
-  }
-
-  pub fn set(val: u32) {
-    unsafe { CurrentStackOffset = val };
-  }
-}
+/// Task priority, tasks with higher values preempt tasks with lower ones.
+pub type Priority = u8;
 
 /// Bytes to reserve in privileged stack based on stack size at the time of task::setup() call.
 static ReservedPivilegedStackSize: u32 = 256;
 
-/// Maximum number of tasks.
-static MaxTasksCount: usize = 4;
+/// Bytes at the bottom of each task stack kept as a scratch pad for
+/// `__morestack` failure, see note on morestack below.
+const StackScratchPadSize: u32 = 8*4;
 
-mod defined_tasks_count {
-  use core::intrinsics::abort;
+/// Pattern task stacks are filled with, to find out how much of them was used.
+const StackPaint: u64 = 0xA5A5_A5A5_A5A5_A5A5;
 
-  /// Total defined tasks count.
-  static mut DefinedTasksCount: usize = 0;
+/// Declares the application tasks and starts the scheduler.
+///
+/// Every task is given as `function(argument): priority P, stack S;`. Its
+/// stack of `S` bytes (rounded down to a multiple of 8) is allocated in the
+/// `.task_stacks` linker section, so the memory used by tasks shows up in the
+/// map file. The number of tasks is fixed at compile time. Tasks are named
+/// after their functions in stack usage and overflow reports.
+///
+/// A task can be made isolated by adding `isolated P`, where `P` is a
+/// `&'static [AddressRange]` of peripherals the task may access, see
+/// `TaskDescriptor::isolated`.
+///
+/// The first task listed runs first. This macro never returns.
+///
+/// ```ignore
+/// static UART0: [AddressRange; 1] =
+///     [AddressRange { start: 0x4000_C000, end: 0x4000_C034 }];
+///
+/// tasks! {
+///   control(0): priority 2, stack 1024;
+///   report(0):  priority 1, stack 512, isolated &UART0;
+/// }
+/// ```
+#[macro_export]
+macro_rules! tasks {
+  ($($task:ident($arg:expr): priority $priority:expr, stack $size:expr
+      $(, isolated $peripherals:expr)*;)+) => ({
+    $(
+      #[allow(non_snake_case)]
+      mod $task {
+        #[link_section=".task_stacks"]
+        pub static mut STACK: [u64; ($size) / 8] = [0; ($size) / 8];
+      }
+    )+
 
-  pub fn get() -> usize {
-    unsafe { DefinedTasksCount }
-  }
+    let mut tasks = unsafe { [$(
+      $crate::os::task::TaskDescriptor::new(
+          stringify!($task), $task, $arg, &mut $task::STACK, $priority)
+          $(.isolated($peripherals))*,
+    )+] };
+    $crate::os::task::setup(&mut tasks)
+  })
+}
 
-  pub fn increase() {
-    unsafe {
-      DefinedTasksCount += 1;
-      if DefinedTasksCount > super::MaxTasksCount {
-        abort();
-      }
-    }
-  }
+/// A range of addresses, `start..end`.
+#[derive(Clone, Copy)]
+pub struct AddressRange {
+  pub start: u32,
+  pub end: u32,
 }
 
 pub enum Status {
@@ -72,9 +133,38 @@ pub enum Status {
 
 /// Task descriptor, provides task stack pointer.
 pub struct TaskDescriptor {
+  pub name: &'static str,
   pub stack_start: u32,
   pub stack_end: u32,
-  pub status: Status
+  /// Lowest address of the stack memory.
+  stack_bottom: u32,
+  /// Address right above the stack memory.
+  stack_base: u32,
+  pub status: Status,
+  /// Effective priority, raised above `base_priority` while the task holds a
+  /// mutex that a higher priority task waits for.
+  pub priority: Priority,
+  /// Priority the task was defined with.
+  pub base_priority: Priority,
+  /// Mutexes held by the task, linked through the mutexes.
+  pub held_mutexes: *const Mutex,
+  /// Mutex the task waits for.
+  pub blocked_on: *const Mutex,
+  /// Tick to wake up at, if sleeping.
+  wake_at: u32,
+  /// Next task sleeping in the same timer wheel slot.
+  next_sleeping: *mut TaskDescriptor,
+  /// Set when the task was woken up by the timer wheel.
+  timed_out: bool,
+  /// Whether the task runs unprivileged with restricted memory access.
+  isolated: bool,
+  /// Peripherals an isolated task may access.
+  peripherals: &'static [AddressRange],
+  /// Where to return to from the service the task runs, 0 if it runs none.
+  pub syscall_return: u32,
+  /// Nesting depth of the critical sections the kernel keeps for the task,
+  /// if it runs unprivileged.
+  critical: u8,
 }
 
 impl TaskDescriptor {
@@ -82,110 +172,308 @@ impl TaskDescriptor {
     self.status = Blocked;
     sched::switch_context();
   }
-  pub fn unblock(&mut self, _: &NoInterrupts) { self.status = Runnable; }
+
+  /// Blocks the task until it is unblocked or the deadline passes.
+  ///
+  /// Returns false if the deadline passed. In this case the caller is
+  /// responsible for removing the task from any waiting list it was put on.
+  pub fn block_until(&mut self, deadline: Instant, crit: NoInterrupts) -> bool {
+    if deadline.remaining().is_none() {
+      return false;
+    }
+    self.timed_out = false;
+    unsafe { timer_wheel::insert(self, deadline.ticks(), &crit) };
+    self.block(crit);
+
+    let crit = NoInterrupts::new();
+    if self.timed_out {
+      false
+    } else {
+      unsafe { timer_wheel::remove(self, &crit) };
+      true
+    }
+  }
+
+  /// Changes the effective priority of the task and reschedules.
+  pub fn set_priority(&mut self, priority: Priority, _: &NoInterrupts) {
+    if self.priority != priority {
+      self.priority = priority;
+      sched::switch_context();
+    }
+  }
+
+  /// Marks the task runnable.
+  ///
+  /// If the task has a higher priority than the current one, a context switch
+  /// is requested. It happens as soon as the critical section ends.
+  pub fn unblock(&mut self, _: &NoInterrupts) {
+    self.status = Runnable;
+    let preempt = unsafe {
+      Tasks.idle() || self.priority > Tasks.current_task().priority
+    };
+    if preempt {
+      sched::switch_context();
+    }
+  }
 }
 
 struct TasksCollection {
   pub current_task: usize,
-  pub tasks: [TaskDescriptor; MaxTasksCount],
+  tasks: *mut TaskDescriptor,
+  count: usize,
 }
 
 pub static mut Tasks: TasksCollection = TasksCollection {
   current_task: 0,
-  tasks: [TaskDescriptor { stack_start: 0, stack_end: 0, status: Runnable }; MaxTasksCount]
+  tasks: 0 as *mut TaskDescriptor,
+  count: 0,
+};
+
+/// Words in the default stack of the idle task.
+///
+/// The idle task is interrupted with up to 26 words of exception frame, with
+/// the FPU state of Cortex-M4/M7, and 9 of software state, on top of
+/// `tickless::sleep` and the trace records it makes. Use `set_idle_stack`
+/// for more.
+#[allow(non_upper_case_globals)]
+const IdleStackSize: usize = 64;
+
+/// Stack of the idle task.
+#[link_section=".task_stacks"]
+static mut IdleStack: [u64; IdleStackSize] = [0; IdleStackSize];
+
+/// Stack the idle task is set up with.
+static mut IdleTaskStack: Option<&'static mut [u64]> = None;
+
+/// Task that runs when all other tasks are blocked.
+static mut IdleTask: TaskDescriptor = TaskDescriptor {
+  name: "",
+  stack_start: 0,
+  stack_end: 0,
+  stack_bottom: 0,
+  stack_base: 0,
+  status: Runnable,
+  priority: 0,
+  base_priority: 0,
+  held_mutexes: 0 as *const Mutex,
+  blocked_on: 0 as *const Mutex,
+  wake_at: 0,
+  next_sleeping: 0 as *mut TaskDescriptor,
+  timed_out: false,
+  isolated: false,
+  peripherals: &[],
+  syscall_return: 0,
+  critical: 0,
 };
 
+/// What the idle task does while no task is runnable.
+#[derive(Clone, Copy)]
+pub enum IdleMode {
+  /// Sleeps, waking up on every tick.
+  Tick,
+  /// Stops ticking until the next sleeping task is due or an interrupt
+  /// occurs, and sleeps meanwhile.
+  Tickless,
+  /// Like `Tickless`, but enters deep sleep. SysTick must keep running in
+  /// the deep sleep mode of the MCU, or tasks only wake up on other
+  /// interrupts.
+  TicklessDeepSleep,
+}
+
+static mut Idle: IdleMode = IdleMode::Tick;
+
+/// Sets what the idle task does, the default is `IdleMode::Tick`.
+pub fn set_idle_mode(mode: IdleMode) {
+  unsafe { Idle = mode };
+}
+
+/// Sets the stack of the idle task, instead of a `IdleStackSize` words one.
+///
+/// Must be called before `setup`.
+pub fn set_idle_stack(stack: &'static mut [u64]) {
+  unsafe { IdleTaskStack = Some(stack) };
+}
+
+fn idle(_: u32) {
+  loop {
+    match unsafe { Idle } {
+      IdleMode::Tick => wfi(),
+      IdleMode::Tickless => tickless::sleep(false),
+      IdleMode::TicklessDeepSleep => tickless::sleep(true),
+    }
+  }
+}
+
 impl TasksCollection {
   pub fn current_task<'a>(&'a mut self) -> &'a mut TaskDescriptor {
-    &mut self.tasks[self.current_task]
+    let index = self.current_task;
+    self.task(index)
   }
 
+  fn task<'a>(&'a mut self, index: usize) -> &'a mut TaskDescriptor {
+    if index == self.count {
+      unsafe { &mut IdleTask }
+    } else {
+      unsafe { &mut *self.tasks.offset(index as isize) }
+    }
+  }
+
+  /// Returns true if no task is runnable and the idle task runs.
+  fn idle(&self) -> bool {
+    self.current_task == self.count
+  }
+
+  /// Switches to the runnable task with the highest priority.
+  ///
+  /// Tasks of the same priority are scheduled round-robin, starting with the
+  /// one after the current task, or with the first one after the idle task.
+  /// The current task keeps running only if there is no other runnable task
+  /// with the same or higher priority. If no task is runnable, the idle task
+  /// runs.
   fn next_task(&mut self) {
-    loop {
-      self.current_task += 1;
-      if self.current_task == defined_tasks_count::get() {
-        self.current_task = 0;
-      }
-      match self.current_task() {
-        &task if !task.valid()                 => {}
-        &TaskDescriptor {status: Runnable, ..} => break,
-        _                                      => {}
+    let count = self.count;
+    let mut next: Option<(usize, Priority)> = None;
+    // The idle task is at index count, so the scan starts at task 0 after it.
+    let first = (self.current_task + 1) % (count + 1);
+
+    for i in 0..count {
+      let index = (first + i) % count;
+      let task = self.task(index);
+      match task.status {
+        Runnable if task.valid() => {},
+        _ => continue,
       }
+      next = match next {
+        Some((n, p)) if p >= task.priority => Some((n, p)),
+        _ => Some((index, task.priority)),
+      };
     }
-  }
 
-  fn add_task(&mut self, t: TaskDescriptor) {
-    self.tasks[defined_tasks_count::get()] = t;
-    defined_tasks_count::increase();
+    match next {
+      Some((n, _)) => self.current_task = n,
+      None => self.current_task = count,
+    }
   }
 }
 
 /// Initialize and start task manager.
 ///
-/// This function keeps main stack intact. It starts the task scheduler and
+/// This function keeps main stack intact, which is where `tasks!` keeps the
+/// task descriptors. It starts the task scheduler with the first task and
 /// never returns.
-///
-/// t should point to initial task.
 #[inline(never)]
-pub fn setup(t: Task, stack_size: u32) {
-  systick::setup(::hal::cortex_m3::systick::CALIBRATED, true);
+pub fn setup(tasks: &mut [TaskDescriptor]) -> ! {
+  match systick_reload() {
+    Some(reload) => {
+      systick::setup(reload);
+      tickless::init(reload);
+    },
+    None => unsafe { abort() },
+  }
+  systick::enable_irq();
 
-  let current_stack = sched::get_current_stack_pointer();
-  // User tasks start at this current stack size + reserved size aligned by 4
-  // bytes.
-  let task_stack_base: u32 = (current_stack as u32 - ReservedPivilegedStackSize) & !3;
-  current_stack_offset::set(task_stack_base);
+  unsafe {
+    let stack = match IdleTaskStack.take() {
+      Some(stack) => stack,
+      None => &mut IdleStack,
+    };
+    IdleTask = TaskDescriptor::new("idle", idle, 0, stack, 0);
+  }
+  isolation::setup(tasks);
 
-  let td = define_task(t, 0, stack_size, true);
+  {
+    let initial = &mut tasks[0];
+    // The first context switch saves software registers of the initial task,
+    // so they must not be reserved on its stack in advance.
+    initial.stack_start += sched::SoftwareStateSize;
+    initial.load();
+  }
 
-  td.load();
+  unsafe {
+    Tasks.tasks = tasks.as_mut_ptr();
+    Tasks.count = tasks.len();
+    Tasks.current_task = 0;
+  }
 
   systick::enable();
   sched::switch_context();
 
-  unsafe { abort() };
+  unsafe { abort() }
 }
 
-#[inline(never)]
-pub fn define_task(t: Task, arg: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
-  systick::disable_irq();
-  let task_base = current_stack_offset::get();
-  let task_stack_size: u32 = (
-    stack_size +
-    8*4 +  // hw saved regs
-    8*4 +  // sw saved regs
-    8*4    // scratch pad for __morestack failure. see note on morestack below.
-  ) & !0b1111;
-  current_stack_offset::set(task_base - task_stack_size);
-
-  let td = TaskDescriptor::new(t, arg, task_base, stack_size, initial);
-  unsafe { Tasks.add_task(td) };
-
-  systick::enable_irq();
-  td
+/// Returns the SysTick reload value for ticks of `TickPeriodMs`, scaled from
+/// the 10ms calibration value.
+fn systick_reload() -> Option<u32> {
+  match systick::ten_ms() {
+    Some(ten_ms) => {
+      let reload = (ten_ms + 1) as u64 * time::TickPeriodMs as u64 / 10 - 1;
+      if reload <= 0x00FF_FFFF { Some(reload as u32) } else { None }
+    },
+    None => None,
+  }
 }
 
 impl TaskDescriptor {
-  /// Creates a new TaskDescriptor for given task, arg and stack base.
+  /// Creates a new TaskDescriptor for given task, arg and stack.
   ///
-  /// This function initializes task stack with hw saved registers.
+  /// This function paints the task stack, initializes it with hw saved
+  /// registers and reserves space for sw saved ones.
   #[inline(never)]
-  pub fn new(t: Task, arg: u32, stack_base: u32, stack_size: u32, initial: bool) -> TaskDescriptor {
+  pub fn new(name: &'static str, t: Task, arg: u32, stack: &'static mut [u64],
+      priority: Priority) -> TaskDescriptor {
+    for word in stack.iter_mut() {
+      *word = StackPaint;
+    }
+
     let state = sched::SavedState::new(t, arg);
+    let stack_bottom = stack.as_mut_ptr() as u32;
+    let stack_base = stack_bottom + (stack.len() * size_of::<u64>()) as u32;
 
-    let mut stack_top: u32 = stack_base - size_of::<sched::SavedState>() as u32;
+    let stack_top: u32 = stack_base - size_of::<sched::SavedState>() as u32;
     unsafe { *(stack_top as *mut sched::SavedState) = state };
-    if !initial {
-      stack_top -= 8*4;
-    }
+    let stack_start = stack_top - sched::SoftwareStateSize;
+    unsafe { sched::init_software_state(stack_start) };
 
     TaskDescriptor {
-      stack_start: stack_top,
-      stack_end: stack_base - stack_size,
+      name: name,
+      stack_start: stack_start,
+      stack_end: stack_bottom + StackScratchPadSize,
+      stack_bottom: stack_bottom,
+      stack_base: stack_base,
       status: Runnable,
+      priority: priority,
+      base_priority: priority,
+      held_mutexes: 0 as *const Mutex,
+      blocked_on: 0 as *const Mutex,
+      wake_at: 0,
+      next_sleeping: 0 as *mut TaskDescriptor,
+      timed_out: false,
+      isolated: false,
+      peripherals: &[],
+      syscall_return: 0,
+      critical: 0,
     }
   }
 
+  /// Makes the task isolated.
+  ///
+  /// An isolated task runs unprivileged. It can write to its own stack and
+  /// the given peripherals, and read the rest of RAM and ROM. Its critical
+  /// sections are kept by the kernel, which disables all interrupts in the
+  /// NVIC meanwhile. The kernel primitives are used through the services of
+  /// `os::syscall`. An isolated task that makes an invalid access is killed.
+  ///
+  /// MPU regions are powers of two, so the task may be able to write up to an
+  /// eighth of the region size past its stack or peripherals, unless they are
+  /// aligned. Not available on Cortex-M0, which has no MPU.
+  #[cfg(not(feature = "cpu_cortex-m0"))]
+  pub fn isolated(mut self, peripherals: &'static [AddressRange])
+      -> TaskDescriptor {
+    self.isolated = true;
+    self.peripherals = peripherals;
+    self
+  }
+
   pub fn load(&self) {
     sched::set_task_stack_pointer(self.stack_start);
     stack::set_stack_limit(self.stack_end);
@@ -195,6 +483,85 @@ impl TaskDescriptor {
     self.stack_start = sched::get_task_stack_pointer();
   }
 
+  /// Returns the stack size in bytes.
+  pub fn stack_size(&self) -> u32 {
+    self.stack_base - self.stack_bottom
+  }
+
+  /// Returns the largest number of stack bytes the task has used so far.
+  ///
+  /// The stack is scanned for the first word that doesn't hold the paint
+  /// pattern, so the result may be a few bytes short.
+  pub fn stack_usage(&self) -> u32 {
+    let mut addr = self.stack_bottom;
+    while addr < self.stack_base &&
+        unsafe { *(addr as *const u64) } == StackPaint {
+      addr += size_of::<u64>() as u32;
+    }
+    self.stack_base - addr
+  }
+
+  /// Returns true if the task runs privileged, either because it is not
+  /// isolated or because it runs a service.
+  pub fn privileged(&self) -> bool {
+    !self.isolated || self.syscall_return != 0
+  }
+
+  /// Returns true if the task may access `len` bytes at `addr`, for writing
+  /// if `write` is set.
+  pub fn can_access(&self, addr: u32, len: u32, write: bool) -> bool {
+    if !self.isolated {
+      return true;
+    }
+    let end = match addr.checked_add(len) {
+      Some(end) => end,
+      None => return false,
+    };
+    if addr >= self.stack_bottom && end <= self.stack_base {
+      return true;
+    }
+    if self.peripherals.iter().any(|p| addr >= p.start && end <= p.end) {
+      return true;
+    }
+    !write && isolation::readable(addr, end)
+  }
+
+  /// Enters a critical section on behalf of the unprivileged task, by
+  /// disabling all interrupts in the NVIC.
+  ///
+  /// Returns false if the sections are nested too deep.
+  pub fn enter_critical(&mut self) -> bool {
+    match self.critical {
+      0 => isolation::disable_irqs(),
+      0xff => return false,
+      _ => {},
+    }
+    self.critical += 1;
+    true
+  }
+
+  /// Leaves a critical section entered with `enter_critical`. Interrupts are
+  /// enabled again when the outermost one ends, and the context switches
+  /// held back meanwhile happen.
+  ///
+  /// Returns false if the task is in no critical section.
+  pub fn leave_critical(&mut self) -> bool {
+    if self.critical == 0 {
+      return false;
+    }
+    self.critical -= 1;
+    if self.critical == 0 {
+      isolation::enable_irqs();
+      sched::switch_context();
+    }
+    true
+  }
+
+  /// Returns true if the task is in a critical section kept by the kernel.
+  pub fn in_critical(&self) -> bool {
+    self.critical != 0
+  }
+
   pub fn valid(&self) -> bool {
     self.stack_end != 0
   }
@@ -204,32 +571,501 @@ impl TaskDescriptor {
   }
 }
 
+/// Stack usage of a task.
+#[derive(Clone, Copy)]
+pub struct StackUsage {
+  /// Name of the task.
+  pub name: &'static str,
+  /// Largest number of bytes used so far.
+  pub used: u32,
+  /// Stack size in bytes.
+  pub size: u32,
+}
+
+/// Returns the stack usage of the task with the given index, in the order
+/// the tasks were declared in, or `None` if there is no such task.
+pub fn stack_usage(index: usize) -> Option<StackUsage> {
+  unsafe {
+    if index >= Tasks.count {
+      return None;
+    }
+    let task = Tasks.task(index);
+    Some(StackUsage {
+      name: task.name,
+      used: task.stack_usage(),
+      size: task.stack_size(),
+    })
+  }
+}
+
+/// Returns the name of the task at `index`, in the order the tasks were
+/// declared in. The idle task comes last, named `idle`.
+pub fn task_name(index: usize) -> Option<&'static str> {
+  unsafe {
+    if index > Tasks.count {
+      return None;
+    }
+    Some(Tasks.task(index).name)
+  }
+}
+
+/// Returns the SysTick reload value of a scheduler tick.
+///
+/// Unlike `systick::get_reload`, this is not affected by tickless idle
+/// reprogramming SysTick.
+pub fn tick_reload() -> u32 {
+  tickless::reload()
+}
+
+/// Puts the current task to sleep for the given duration.
+pub fn sleep(duration: Duration) {
+  sleep_until(Instant::now() + duration);
+}
+
+/// Puts the current task to sleep until the given instant.
+///
+/// Returns immediately if the instant has already passed.
+pub fn sleep_until(deadline: Instant) {
+  let crit = NoInterrupts::new();
+  unsafe { Tasks.current_task().block_until(deadline, crit) };
+}
+
+/// A list of tasks waiting for something.
+pub type WaitList = Queue<*mut TaskDescriptor>;
+
+/// Blocks the current task on `list` until woken up or the deadline passes.
+///
+/// Returns false if the deadline passed.
+pub unsafe fn wait(list: &WaitList, deadline: Option<Instant>,
+    crit: NoInterrupts) -> bool {
+  let task = Tasks.current_task();
+  let mut waiting = Node::new(&mut *task as *mut TaskDescriptor);
+  list.push(&mut waiting, &crit);
+
+  let woken = match deadline {
+    None => {
+      task.block(crit);
+      true
+    },
+    Some(deadline) => task.block_until(deadline, crit),
+  };
+
+  // A task that woke us up has removed us from the list already, but after a
+  // timeout we are still there. If we were woken up between the timeout and
+  // running again, the wake up counts.
+  let crit = NoInterrupts::new();
+  let still_waiting = list.remove(&mut waiting, &crit);
+  woken || !still_waiting
+}
+
+/// Wakes up the task waiting on `list` with the highest priority, the first
+/// one to wait among equals.
+///
+/// Returns false if there was no task to wake up.
+pub unsafe fn wake_one(list: &WaitList, crit: &NoInterrupts) -> bool {
+  match highest_waiter(list) {
+    Some(node) => {
+      list.remove(node, crit);
+      (*(*node).data).unblock(crit);
+      true
+    },
+    None => false,
+  }
+}
+
+/// Returns the waiting task with the highest priority, the first one to
+/// wait wins among equals.
+pub unsafe fn highest_waiter(list: &WaitList)
+    -> Option<*mut Node<*mut TaskDescriptor>> {
+  let mut best: Option<*mut Node<*mut TaskDescriptor>> = None;
+  let mut node = *list.head.get();
+  while !node.is_null() {
+    best = match best {
+      Some(b) if (*(*b).data).priority >= (*(*node).data).priority =>
+        Some(b),
+      _ => Some(node),
+    };
+    node = *(*node).next.get();
+  }
+  best
+}
+
+/// Sleeping tasks, hashed by their wake up tick.
+mod timer_wheel {
+  use core::option::Option::{self, Some, None};
+
+  use super::{NoInterrupts, TaskDescriptor};
+
+  const Slots: usize = 16;
+
+  static mut Wheel: [*mut TaskDescriptor; Slots] =
+      [0 as *mut TaskDescriptor; Slots];
+
+  /// Adds the task to the wheel. The task must be blocked right after.
+  pub unsafe fn insert(task: &mut TaskDescriptor, wake_at: u32,
+      _: &NoInterrupts) {
+    let slot = &mut Wheel[wake_at as usize % Slots];
+    task.wake_at = wake_at;
+    task.next_sleeping = *slot;
+    *slot = task;
+  }
+
+  /// Removes the task from the wheel, if it is there.
+  pub unsafe fn remove(task: &mut TaskDescriptor, _: &NoInterrupts) {
+    let target = &mut *task as *mut TaskDescriptor;
+    let mut link: *mut *mut TaskDescriptor =
+        &mut Wheel[task.wake_at as usize % Slots];
+    while !(*link).is_null() {
+      if *link == target {
+        *link = task.next_sleeping;
+        task.next_sleeping = 0 as *mut TaskDescriptor;
+        return;
+      }
+      link = &mut (**link).next_sleeping;
+    }
+  }
+
+  /// Returns the number of ticks from `now` until the first sleeping task is
+  /// due, or `None` if no task is sleeping.
+  pub unsafe fn next_due(now: u32, _: &NoInterrupts) -> Option<u32> {
+    let mut next: Option<u32> = None;
+    for slot in Wheel.iter() {
+      let mut task = *slot;
+      while !task.is_null() {
+        let due = (*task).wake_at.wrapping_sub(now);
+        next = match next {
+          Some(n) if n <= due => Some(n),
+          _ => Some(due),
+        };
+        task = (*task).next_sleeping;
+      }
+    }
+    next
+  }
+
+  /// Unblocks all tasks that are due at `now`.
+  ///
+  /// Tasks in the slot that are due in later rounds of the wheel stay there.
+  pub unsafe fn expire(now: u32, crit: &NoInterrupts) {
+    let mut link: *mut *mut TaskDescriptor = &mut Wheel[now as usize % Slots];
+    while !(*link).is_null() {
+      let task = &mut **link;
+      if task.wake_at == now {
+        *link = task.next_sleeping;
+        task.next_sleeping = 0 as *mut TaskDescriptor;
+        task.timed_out = true;
+        task.unblock(crit);
+      } else {
+        link = &mut task.next_sleeping;
+      }
+    }
+  }
+}
+
+/// Skipping ticks while all tasks are blocked.
+///
+/// SysTick is reprogrammed to fire when the next sleeping task is due. If an
+/// interrupt wakes the core up earlier, SysTick is restarted at the next tick
+/// boundary. Either way, the skipped ticks are accounted for on wake up.
+mod tickless {
+  use core::cmp::{min, max};
+  use core::mem::drop;
+  use core::option::Option::{Some, None};
+
+  use os::time::{self, Instant};
+  use util::support::wfi;
+  use super::{NoInterrupts, scb, systick, timer_wheel};
+
+  /// SysTick reload value for one tick.
+  static mut TickReload: u32 = 0;
+
+  pub fn init(reload: u32) {
+    unsafe { TickReload = reload };
+  }
+
+  pub fn reload() -> u32 {
+    unsafe { TickReload }
+  }
+
+  /// Sleeps until the next sleeping task is due or an interrupt occurs.
+  pub fn sleep(deep: bool) {
+    let crit = NoInterrupts::new();
+    let period = unsafe { TickReload } + 1;
+    let max_ticks = 0x00FF_FFFF / period;
+    let now = Instant::now().ticks();
+    let ticks = match unsafe { timer_wheel::next_due(now, &crit) } {
+      Some(ticks) => min(ticks, max_ticks),
+      None => max_ticks,
+    };
+
+    systick::disable();
+    if ticks < 2 || scb::systick_pending() {
+      // Nothing to skip, the next tick is due anyway.
+      systick::enable();
+      drop(crit);
+      wfi();
+      return;
+    }
+
+    // Sleep for the rest of the current tick and the skipped ones. SysTick
+    // loads the long reload value right away, and the tick reload value when
+    // it wraps.
+    let current = systick::get_current();
+    let length = current + (ticks - 1) * period;
+    systick::set_reload(length - 1);
+    systick::restart();
+    systick::enable();
+    systick::set_reload(period - 1);
+
+    // Interrupts are masked, but still wake the core up. They run once the
+    // critical section ends.
+    if deep {
+      scb::set_sleep_deep(true);
+    }
+    wfi();
+    if deep {
+      scb::set_sleep_deep(false);
+    }
+
+    systick::disable();
+    let skipped = if scb::systick_pending() {
+      // Slept until the end, the pending SysTick accounts for the last tick.
+      ticks - 1
+    } else {
+      // Woken up early, resume ticking at the next tick boundary.
+      let elapsed = length - 1 - systick::get_current();
+      let (skipped, left) = if elapsed < current {
+        (0, current - elapsed)
+      } else {
+        let past = elapsed - current;
+        (1 + past / period, period - past % period)
+      };
+      systick::set_reload(max(left, 2) - 1);
+      systick::restart();
+      skipped
+    };
+    systick::enable();
+    systick::set_reload(period - 1);
+
+    for _ in 0..skipped {
+      unsafe { timer_wheel::expire(time::tick(), &crit) };
+    }
+  }
+}
+
+/// Per-task memory protection.
+#[cfg(any(feature = "cpu_cortex-m3", feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+mod isolation {
+  use core::intrinsics::abort;
+  use core::iter::Iterator;
+
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::{mpu, nvic, scb, sched};
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::mpu::{Access, Region};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::{mpu, nvic, scb, sched};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::mpu::{Access, Region};
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::{mpu, nvic, scb, sched};
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::mpu::{Access, Region};
+  use super::TaskDescriptor;
+
+  // Higher numbered regions take priority where they overlap.
+  const RamRegion: u8 = 0;
+  const RomRegion: u8 = 1;
+  const StackRegion: u8 = 2;
+  const FirstPeripheralRegion: u8 = 3;
+
+  extern {
+    static __rom_start: u32;
+    static __rom_end: u32;
+    static __ram_start: u32;
+    static __ram_end: u32;
+  }
+
+  /// Sets up the regions shared by all isolated tasks and enables the MPU, if
+  /// any task is isolated.
+  pub fn setup(tasks: &[TaskDescriptor]) {
+    let max_peripherals = tasks.iter()
+        .filter(|t| t.isolated)
+        .map(|t| t.peripherals.len())
+        .max();
+    match max_peripherals {
+      None => return,
+      Some(n) if FirstPeripheralRegion as usize + n >
+          mpu::region_count() as usize => unsafe { abort() },
+      Some(_) => {},
+    }
+
+    unsafe {
+      mpu::set_region(RamRegion, &Region::covering(
+          &__ram_start as *const u32 as u32, &__ram_end as *const u32 as u32,
+          Access::ReadOnly, false, false));
+      mpu::set_region(RomRegion, &Region::covering(
+          &__rom_start as *const u32 as u32, &__rom_end as *const u32 as u32,
+          Access::ReadOnly, true, false));
+    }
+    scb::enable_memmanage_fault();
+    mpu::enable();
+  }
+
+  /// Returns true if `start..end` is in RAM or ROM, which isolated tasks can
+  /// read.
+  pub fn readable(start: u32, end: u32) -> bool {
+    unsafe {
+      let ram = (&__ram_start as *const u32 as u32,
+          &__ram_end as *const u32 as u32);
+      let rom = (&__rom_start as *const u32 as u32,
+          &__rom_end as *const u32 as u32);
+      (start >= ram.0 && end <= ram.1) || (start >= rom.0 && end <= rom.1)
+    }
+  }
+
+  /// Interrupts that were enabled before `disable_irqs`.
+  static mut EnabledIrqs: [u32; 8] = [0; 8];
+
+  /// Disables all interrupts in the NVIC. Exceptions, SysTick and PendSV
+  /// included, still run.
+  ///
+  /// Only one task can be in a critical section, as there are no context
+  /// switches meanwhile.
+  pub fn disable_irqs() {
+    unsafe { EnabledIrqs = nvic::disable_all() };
+  }
+
+  /// Enables the interrupts disabled by `disable_irqs` again.
+  pub fn enable_irqs() {
+    unsafe { nvic::enable_all(&EnabledIrqs) };
+  }
+
+  /// Programs the task specific regions and privilege level.
+  ///
+  /// Regions are left as they are for privileged tasks, as they don't restrict
+  /// privileged access.
+  pub fn load(task: &TaskDescriptor) {
+    sched::set_privileged(task.privileged());
+    if !task.isolated {
+      return;
+    }
+
+    mpu::set_region(StackRegion, &Region::covering(
+        task.stack_bottom, task.stack_base, Access::ReadWrite, false, false));
+    let mut number = FirstPeripheralRegion;
+    for p in task.peripherals {
+      mpu::set_region(number, &Region::covering(
+          p.start, p.end, Access::ReadWrite, false, true));
+      number += 1;
+    }
+    while number < mpu::region_count() {
+      mpu::clear_region(number);
+      number += 1;
+    }
+  }
+}
+
+/// Cortex-M0 has no MPU, tasks can't be made isolated, so they all run
+/// privileged.
+#[cfg(feature = "cpu_cortex-m0")]
+mod isolation {
+  use super::TaskDescriptor;
+
+  pub fn setup(_: &[TaskDescriptor]) {}
+
+  pub fn load(_: &TaskDescriptor) {}
+
+  pub fn readable(_: u32, _: u32) -> bool { false }
+
+  pub fn disable_irqs() {}
+
+  pub fn enable_irqs() {}
+}
+
+/// Kills an isolated task that made an invalid memory access, called from the
+/// MemManage handler in sched.S.
+///
+/// Faults in privileged code are not recoverable, they are reported by
+/// `fault_handler`.
+#[cfg(any(feature = "cpu_cortex-m3", feature = "cpu_cortex-m4",
+          feature = "cpu_cortex-m7"))]
+#[no_mangle]
+pub unsafe extern fn mmfault_handler(frame: *const ExceptionFrame,
+    exc_return: u32) {
+  if scb::returns_to_thread() && Tasks.current_task().isolated {
+    scb::take_memmanage_fault();
+    kill_current_task(0);
+  } else {
+    fault_handler(frame, exc_return);
+  }
+}
+
+/// Runs the scheduler, `tick` is true if called from SysTick.
 #[inline(always)]
-pub unsafe fn task_scheduler() {
+pub unsafe fn task_scheduler(tick: bool) {
+  trace::isr_enter();
   stack::set_stack_limit(stack::stack_base() - ReservedPivilegedStackSize);
   Tasks.current_task().save();
-  Tasks.next_task();
+  if tick {
+    let crit = NoInterrupts::new();
+    timer_wheel::expire(time::tick(), &crit);
+  }
+  let previous = Tasks.current_task;
+  // A task in a critical section kept by the kernel runs until it leaves it.
+  if !Tasks.current_task().in_critical() {
+    Tasks.next_task();
+  }
+  if Tasks.current_task != previous {
+    trace::record(trace::Kind::Switch, previous as u32);
+  }
   Tasks.current_task().load();
+  isolation::load(Tasks.current_task());
+  trace::isr_exit();
 }
 
 // TODO(farcaller): this should not actually use stack!
-// At the time of the call of syscall(), the stack is overflown by 4, we still
+// At the time of the call of report_stack_overflow(), the stack is overflown by 4, we still
 // have 12 bytes in reserve and 2*8*4 to save the frame in pendsv after kill.
 #[no_stack_check]
 pub fn morestack() {
   let psp = sched::get_task_stack_pointer();
   let sp = sched::get_current_stack_pointer();
   if psp == sp {
-    unsafe { syscall(kill_current_task, 0) };
+    report_stack_overflow();
   } else {
     unsafe { abort() };
   }
 }
 
+/// Reports the task that overflowed its stack to the debug output and kills
+/// it.
+///
+/// Runs in the SVCall handler, on the main stack.
+#[doc(hidden)]
+#[inline(never)]
+#[no_stack_check]
+pub fn stack_overflow() {
+  {
+    let task = unsafe { Tasks.current_task() };
+    debug::print("stack overflow in task ");
+    debug::print(task.name);
+    debug::print(", limit 0x");
+    debug::print_hex(task.stack_end);
+    debug::print("\n");
+  }
+  kill_current_task(0);
+}
+
 #[inline(never)]
 #[no_mangle]
 #[no_stack_check]
 pub fn kill_current_task(_: u32) {
-  unsafe { Tasks.current_task().invalidate() };
+  unsafe {
+    let task = Tasks.current_task();
+    while task.leave_critical() {}
+    task.invalidate();
+  }
   sched::switch_context();
 }
@@ -0,0 +1,108 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Scheduler time.
+
+Time is counted in scheduler ticks, driven by SysTick. A tick lasts
+`TickPeriodMs` milliseconds. Without the `multitasking` feature, nothing
+advances the time.
+*/
+
+use core::ops::Add;
+use core::option::Option::{self, Some, None};
+
+/// Length of a scheduler tick in milliseconds.
+#[allow(non_upper_case_globals)]
+pub const TickPeriodMs: u32 = 10;
+
+/// Ticks elapsed since the scheduler started.
+#[allow(non_upper_case_globals)]
+static mut Ticks: u32 = 0;
+
+/// A span of time.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration {
+  ticks: u32,
+}
+
+impl Duration {
+  /// Creates a duration of the given number of ticks.
+  pub fn from_ticks(ticks: u32) -> Duration {
+    Duration { ticks: ticks }
+  }
+
+  /// Creates a duration of the given number of milliseconds, rounded up to
+  /// whole ticks.
+  pub fn from_ms(ms: u32) -> Duration {
+    let ticks = ms / TickPeriodMs + if ms % TickPeriodMs != 0 { 1 } else { 0 };
+    Duration { ticks: ticks }
+  }
+
+  /// Returns the duration in ticks.
+  pub fn ticks(self) -> u32 {
+    self.ticks
+  }
+}
+
+/// A point in time.
+///
+/// The tick counter wraps around, so instants are only comparable if they are
+/// less than 2^31 ticks apart.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Instant {
+  ticks: u32,
+}
+
+impl Instant {
+  /// Returns the current time.
+  pub fn now() -> Instant {
+    Instant { ticks: unsafe { Ticks } }
+  }
+
+  /// Returns the tick count of the instant.
+  pub fn ticks(self) -> u32 {
+    self.ticks
+  }
+
+  /// Returns the time left until this instant, or `None` if it has passed.
+  pub fn remaining(self) -> Option<Duration> {
+    let left = self.ticks.wrapping_sub(Instant::now().ticks);
+    if left == 0 || left > i32::max_value() as u32 {
+      None
+    } else {
+      Some(Duration::from_ticks(left))
+    }
+  }
+}
+
+impl Add<Duration> for Instant {
+  type Output = Instant;
+
+  fn add(self, rhs: Duration) -> Instant {
+    Instant { ticks: self.ticks.wrapping_add(rhs.ticks) }
+  }
+}
+
+/// Advances time by one tick, returning the new tick count.
+///
+/// Called by the scheduler on SysTick.
+#[doc(hidden)]
+pub fn tick() -> u32 {
+  unsafe {
+    Ticks = Ticks.wrapping_add(1);
+    Ticks
+  }
+}
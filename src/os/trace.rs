@@ -0,0 +1,219 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Kernel event tracing.
+
+With the `trace` feature, the scheduler records context switches and tasks
+blocking on mutexes and condition variables into a RAM buffer, keeping the
+last `BufferSize` events. The entry and exit of the SysTick and PendSV
+handlers are recorded by the scheduler. Peripheral interrupt handlers are
+traced by calling `isr_enter` first and `isr_exit` last. Without the feature,
+recording does nothing.
+
+`export` writes the buffer to a `CharIO` and clears it. The stream is decoded
+on the host with `support/trace_decode.py`. All integers are little endian:
+
+ * header: `"ZTRC"`, version (u8, 1), tick period in ms (u8), SysTick reload
+   value (u32), number of events lost to overwriting (u32)
+ * task names: count (u8), then for each task its length (u8) and bytes. The
+   idle task comes last, named `idle`.
+ * events: count (u16), then 16 bytes per event, oldest first: tick (u32),
+   SysTick counts elapsed in the tick (u32), `Kind` (u8), running task (u8),
+   reserved (u16), data (u32)
+*/
+
+pub use os::trace::internal::{record, isr_enter, isr_exit, export};
+
+/// Kind of a trace event.
+#[derive(Clone, Copy)]
+pub enum Kind {
+  /// The scheduler switched tasks, the data is the previous task.
+  Switch = 0,
+  /// The task blocked on the mutex at the address in the data.
+  MutexBlock = 1,
+  /// The task blocked on the condition variable at the address in the data.
+  CondVarBlock = 2,
+  /// An exception handler started, the data is the exception number.
+  IsrEnter = 3,
+  /// An exception handler ended, the data is the exception number.
+  IsrExit = 4,
+}
+
+/// Number of events kept.
+#[allow(non_upper_case_globals)]
+pub const BufferSize: usize = 256;
+
+#[cfg(feature = "trace")]
+mod internal {
+  use core::cmp::min;
+
+  use drivers::chario::CharIO;
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::{scb, systick};
+  #[cfg(feature = "cpu_cortex-m0")]
+  use hal::cortex_m0::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::{scb, systick};
+  #[cfg(feature = "cpu_cortex-m3")]
+  use hal::cortex_m3::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::{scb, systick};
+  #[cfg(feature = "cpu_cortex-m4")]
+  use hal::cortex_m4::irq::NoInterrupts;
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::{scb, systick};
+  #[cfg(feature = "cpu_cortex-m7")]
+  use hal::cortex_m7::irq::NoInterrupts;
+  use os::task::{Tasks, task_name, tick_reload};
+  use os::time::{Instant, TickPeriodMs};
+  use super::{Kind, BufferSize};
+
+  #[derive(Clone, Copy)]
+  struct Event {
+    tick: u32,
+    elapsed: u32,
+    kind: u8,
+    task: u8,
+    data: u32,
+  }
+
+  #[allow(non_upper_case_globals)]
+  static mut Events: [Event; BufferSize] =
+      [Event { tick: 0, elapsed: 0, kind: 0, task: 0, data: 0 }; BufferSize];
+  /// Index the next event is stored at.
+  #[allow(non_upper_case_globals)]
+  static mut Next: usize = 0;
+  /// Events recorded since the buffer was last cleared.
+  #[allow(non_upper_case_globals)]
+  static mut Count: u32 = 0;
+  /// Cleared while exporting.
+  #[allow(non_upper_case_globals)]
+  static mut Enabled: bool = true;
+
+  /// Records an event of the current task.
+  pub fn record(kind: Kind, data: u32) {
+    let _crit = NoInterrupts::new();
+    unsafe {
+      if !Enabled {
+        return;
+      }
+      Events[Next] = Event {
+        tick: Instant::now().ticks(),
+        elapsed: tick_reload().saturating_sub(systick::get_current()),
+        kind: kind as u8,
+        task: Tasks.current_task as u8,
+        data: data,
+      };
+      Next = (Next + 1) % BufferSize;
+      Count = Count.wrapping_add(1);
+    }
+  }
+
+  /// Records the start of the running exception handler.
+  pub fn isr_enter() {
+    record(Kind::IsrEnter, scb::active_exception());
+  }
+
+  /// Records the end of the running exception handler.
+  pub fn isr_exit() {
+    record(Kind::IsrExit, scb::active_exception());
+  }
+
+  /// Writes the recorded events to `out`, and clears the buffer.
+  ///
+  /// Nothing is recorded meanwhile.
+  pub fn export(out: &CharIO) {
+    let (count, next) = {
+      let _crit = NoInterrupts::new();
+      unsafe {
+        Enabled = false;
+        (Count, Next)
+      }
+    };
+    let kept = min(count, BufferSize as u32) as usize;
+
+    out.puts("ZTRC");
+    put_u8(out, 1);
+    put_u8(out, TickPeriodMs as u8);
+    put_u32(out, tick_reload());
+    put_u32(out, count - kept as u32);
+
+    let mut tasks = 0;
+    while task_name(tasks).is_some() {
+      tasks += 1;
+    }
+    put_u8(out, tasks as u8);
+    for i in 0..tasks {
+      let name = task_name(i).unwrap_or("");
+      put_u8(out, name.len() as u8);
+      out.puts(name);
+    }
+
+    put_u16(out, kept as u16);
+    for i in 0..kept {
+      let index = (next + BufferSize - kept + i) % BufferSize;
+      let event = unsafe { Events[index] };
+      put_u32(out, event.tick);
+      put_u32(out, event.elapsed);
+      put_u8(out, event.kind);
+      put_u8(out, event.task);
+      put_u16(out, 0);
+      put_u32(out, event.data);
+    }
+
+    let _crit = NoInterrupts::new();
+    unsafe {
+      Next = 0;
+      Count = 0;
+      Enabled = true;
+    }
+  }
+
+  fn put_u8(out: &CharIO, value: u8) {
+    out.putc(value as char);
+  }
+
+  fn put_u16(out: &CharIO, value: u16) {
+    put_u8(out, value as u8);
+    put_u8(out, (value >> 8) as u8);
+  }
+
+  fn put_u32(out: &CharIO, value: u32) {
+    put_u16(out, value as u16);
+    put_u16(out, (value >> 16) as u16);
+  }
+}
+
+#[cfg(not(feature = "trace"))]
+mod internal {
+  use drivers::chario::CharIO;
+  use super::Kind;
+
+  /// Records an event of the current task (mock)
+  #[inline(always)]
+  pub fn record(_: Kind, _: u32) { }
+
+  /// Records the start of the running exception handler (mock)
+  #[inline(always)]
+  pub fn isr_enter() { }
+
+  /// Records the end of the running exception handler (mock)
+  #[inline(always)]
+  pub fn isr_exit() { }
+
+  /// Writes the recorded events (mock)
+  pub fn export(_: &CharIO) { }
+}
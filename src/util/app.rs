@@ -46,6 +46,6 @@ pub extern fn __morestack() {
 #[no_stack_check]
 #[no_mangle]
 #[cfg(feature = "multitasking")]
-pub unsafe fn task_scheduler() {
-  zinc::os::task::task_scheduler();
+pub unsafe fn task_scheduler(tick: bool) {
+  zinc::os::task::task_scheduler(tick);
 }
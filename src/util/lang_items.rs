@@ -22,6 +22,7 @@ extern fn eh_personality() {}
 
 #[cfg(all(not(test), not(feature = "test")))]
 #[lang="panic_fmt"]
-pub fn panic_fmt(_fmt: &Arguments, _file_line: &(&'static str, usize)) -> ! {
-  loop { }
+pub fn panic_fmt(fmt: &Arguments, file_line: &(&'static str, usize)) -> ! {
+  let &(file, line) = file_line;
+  ::util::panic::handle(*fmt, file, line)
 }
@@ -18,6 +18,7 @@
 pub mod strconv;
 pub mod support;
 pub mod shared;
+pub mod panic;
 #[cfg(feature = "multitasking")] pub mod queue;
 
 mod lang_items;
@@ -0,0 +1,213 @@
+// Zinc, the bare metal stack for rust.
+// Copyright 2014 Vladimir "farcaller" Pouzanov <farcaller@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/*!
+Panic reporting.
+
+A panic prints its message and location through the output set with
+`set_output`, or through the `os::debug` backend if there is none. With
+`set_persist(true)` the message is also kept in the `.noinit` RAM section, so
+it can be read back with `last_panic` after a reset.
+
+After reporting, the system halts or resets, see `set_action`. A panicking
+isolated task is made privileged by the kernel to do so.
+*/
+
+use core::fmt::{self, Arguments, Write};
+use core::option::Option::{self, Some, None};
+use core::result::Result::Ok;
+use core::str;
+
+use drivers::chario::{CharIO, Writer};
+use os::debug;
+use util::support::wfi;
+
+/// What to do after a panic has been reported.
+#[derive(Clone, Copy)]
+pub enum PanicAction {
+  /// Stop, waiting for a debugger.
+  Halt,
+  /// Reset the system.
+  Reset,
+}
+
+#[allow(non_upper_case_globals)]
+static mut Output: Option<&'static CharIO> = None;
+#[allow(non_upper_case_globals)]
+static mut Action: PanicAction = PanicAction::Halt;
+#[allow(non_upper_case_globals)]
+static mut Persist: bool = false;
+#[allow(non_upper_case_globals)]
+static mut Panicking: bool = false;
+
+/// Marks a valid record, "PANC".
+#[allow(non_upper_case_globals)]
+const RecordMagic: u32 = 0x50414e43;
+
+/// A panic message kept across resets.
+struct Record {
+  magic: u32,
+  len: usize,
+  message: [u8; 128],
+}
+
+/// Not cleared on startup, only valid if `magic` is `RecordMagic`.
+#[link_section=".noinit"]
+#[allow(non_upper_case_globals)]
+static mut LastPanic: Record = Record {
+  magic: 0,
+  len: 0,
+  message: [0; 128],
+};
+
+/// Sets the output panics are printed to.
+pub fn set_output(output: &'static CharIO) {
+  unsafe { Output = Some(output) };
+}
+
+/// Sets what to do after a panic, the default is `PanicAction::Halt`.
+pub fn set_action(action: PanicAction) {
+  unsafe { Action = action };
+}
+
+/// Sets whether panic messages are kept in RAM across resets.
+pub fn set_persist(persist: bool) {
+  unsafe { Persist = persist };
+}
+
+/// Returns the message of the panic kept before the last reset, if any.
+///
+/// Messages longer than 128 bytes are truncated.
+pub fn last_panic() -> Option<&'static str> {
+  unsafe {
+    if LastPanic.magic != RecordMagic ||
+        LastPanic.len > LastPanic.message.len() {
+      return None;
+    }
+    match str::from_utf8(&LastPanic.message[..LastPanic.len]) {
+      Ok(message) => Some(message),
+      _ => None,
+    }
+  }
+}
+
+/// Forgets the kept panic message.
+pub fn clear_last_panic() {
+  unsafe { LastPanic.magic = 0 };
+}
+
+struct DebugWriter;
+
+impl Write for DebugWriter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    debug::print(s);
+    Ok(())
+  }
+}
+
+/// Appends to `LastPanic`, dropping what doesn't fit.
+struct RecordWriter;
+
+impl Write for RecordWriter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    unsafe {
+      let len = LastPanic.len;
+      let mut n = LastPanic.message.len() - len;
+      if n >= s.len() {
+        n = s.len();
+      } else {
+        while !s.is_char_boundary(n) {
+          n -= 1;
+        }
+      }
+      let bytes = s.as_bytes();
+      for i in 0..n {
+        LastPanic.message[len + i] = bytes[i];
+      }
+      LastPanic.len = len + n;
+    }
+    Ok(())
+  }
+}
+
+fn write_report<W: Write>(w: &mut W, msg: Arguments, file: &str, line: usize) {
+  let _ = write!(w, "panicked at '{}', {}:{}", msg, file, line);
+}
+
+/// Reports a panic, then halts or resets.
+#[doc(hidden)]
+pub fn handle(msg: Arguments, file: &str, line: usize) -> ! {
+  unsafe {
+    disable_irqs();
+
+    // A panic while reporting one, don't try again.
+    if Panicking {
+      stop();
+    }
+    Panicking = true;
+
+    if Persist {
+      LastPanic.len = 0;
+      write_report(&mut RecordWriter, msg, file, line);
+      LastPanic.magic = RecordMagic;
+    }
+
+    match Output {
+      Some(out) => {
+        write_report(&mut Writer(out), msg, file, line);
+        out.puts("\n");
+      },
+      None => {
+        write_report(&mut DebugWriter, msg, file, line);
+        debug::print("\n");
+      },
+    }
+
+    stop();
+  }
+}
+
+unsafe fn stop() -> ! {
+  match Action {
+    PanicAction::Reset => reset(),
+    PanicAction::Halt => loop { wfi() },
+  }
+}
+
+#[cfg(feature = "cpu_cortex-m0")]
+fn reset() -> ! { ::hal::cortex_m0::scb::reset() }
+#[cfg(feature = "cpu_cortex-m3")]
+fn reset() -> ! { ::hal::cortex_m3::scb::reset() }
+#[cfg(feature = "cpu_cortex-m4")]
+fn reset() -> ! { ::hal::cortex_m4::scb::reset() }
+#[cfg(feature = "cpu_cortex-m7")]
+fn reset() -> ! { ::hal::cortex_m7::scb::reset() }
+#[cfg(not(any(feature = "cpu_cortex-m0", feature = "cpu_cortex-m3",
+              feature = "cpu_cortex-m4", feature = "cpu_cortex-m7")))]
+fn reset() -> ! { loop {} }
+
+#[cfg(feature = "multitasking")]
+unsafe fn disable_irqs() {
+  ::os::syscall::disable_irqs_for_panic();
+}
+
+#[cfg(all(target_arch = "arm", not(feature = "multitasking")))]
+#[inline(always)]
+unsafe fn disable_irqs() {
+  asm!("cpsid i" :::: "volatile");
+}
+
+#[cfg(all(not(target_arch = "arm"), not(feature = "multitasking")))]
+unsafe fn disable_irqs() {}
@@ -6,31 +6,37 @@
 // | |--->| |--->| |--->| |--->| |
 //
 
-use core::ty::Unsafe;
+use core::cell::UnsafeCell;
 use core::cmp::Ord;
 use core::ops::Deref;
-use core::ptr::RawPtr;
 use core::option::Option::{self, Some, None};
 
-use hal::cortex_m3::sched::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m0")]
+use hal::cortex_m0::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m3")]
+use hal::cortex_m3::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m4")]
+use hal::cortex_m4::irq::NoInterrupts;
+#[cfg(feature = "cpu_cortex-m7")]
+use hal::cortex_m7::irq::NoInterrupts;
 
 pub struct Node<T> {
-  pub next: Unsafe<*mut Node<T>>,
+  pub next: UnsafeCell<*mut Node<T>>,
   pub data: T
 }
 
 pub struct Queue<T> {
-  pub head: Unsafe<*mut Node<T>>,
-  pub tail: Unsafe<*mut Node<T>>
+  pub head: UnsafeCell<*mut Node<T>>,
+  pub tail: UnsafeCell<*mut Node<T>>
 }
 
 fn null_mut<T>() -> *mut T { 0 as *mut T }
 
 impl<T> Queue<T> {
-  pub fn new() -> Queue<T> {
+  pub const fn new() -> Queue<T> {
     Queue {
-      head: Unsafe::new(null_mut()),
-      tail: Unsafe::new(null_mut())
+      head: UnsafeCell::new(0 as *mut Node<T>),
+      tail: UnsafeCell::new(0 as *mut Node<T>)
     }
   }
 
@@ -63,16 +69,44 @@ impl<T> Queue<T> {
     if (*head).is_null() {
       None
     } else {
-      *head = *(**head).next.get();
-      Some(*head)
+      let node = *head;
+      *head = *(*node).next.get();
+      if (*head).is_null() {
+        *self.tail.get() = null_mut();
+      }
+      Some(node)
+    }
+  }
+
+  /// Remove a node from anywhere in the queue.
+  ///
+  /// Returns false if the node was not queued.
+  pub unsafe fn remove(&self, node: *mut Node<T>, _: &NoInterrupts) -> bool {
+    let mut prev: *mut Node<T> = null_mut();
+    let mut next: &UnsafeCell<*mut Node<T>> = &self.head;
+    loop {
+      let i: *mut Node<T> = *next.get();
+      if i.is_null() {
+        return false;
+      }
+      if i == node {
+        break;
+      }
+      prev = i;
+      next = &(*i).next;
+    }
+    *next.get() = *(*node).next.get();
+    if *self.tail.get() == node {
+      *self.tail.get() = prev;
     }
+    true
   }
 }
 
 impl<T: Ord> Queue<T> {
   /// Priority insertion (higher ends up closer to head).
   pub unsafe fn insert(&self, node: *mut Node<T>, _: &NoInterrupts) {
-    let mut next: &Unsafe<*mut Node<T>> = &self.head;
+    let mut next: &UnsafeCell<*mut Node<T>> = &self.head;
     loop {
       let i: *mut Node<T> = *next.get();
       if i.is_null() {
@@ -93,10 +127,11 @@ impl<T: Ord> Queue<T> {
 
 impl<T> Node<T> {
   pub fn new(data: T) -> Node<T> {
-    Node { next: Unsafe::new(null_mut()), data: data }
+    Node { next: UnsafeCell::new(null_mut()), data: data }
   }
 }
 
-impl<T> Deref<T> for Node<T> {
+impl<T> Deref for Node<T> {
+  type Target = T;
   fn deref<'a>(&'a self) -> &'a T {&self.data}
 }
@@ -0,0 +1,94 @@
+#!/usr/bin/env python
+
+"""Decodes a kernel trace written by zinc::os::trace::export into a timeline.
+
+Usage: trace_decode.py TRACE_FILE
+
+The trace file is the raw byte stream, e.g. captured from the UART.
+"""
+
+import struct, sys
+
+KINDS = ['switch', 'mutex block', 'condvar block', 'isr enter', 'isr exit']
+EXCEPTIONS = {2: 'NMI', 3: 'HardFault', 11: 'SVCall', 14: 'PendSV',
+              15: 'SysTick'}
+
+class Reader(object):
+  def __init__(self, data):
+    self.data = data
+    self.pos = 0
+
+  def read(self, fmt):
+    size = struct.calcsize(fmt)
+    if self.pos + size > len(self.data):
+      raise RuntimeError('trace truncated at offset {}'.format(self.pos))
+    values = struct.unpack_from(fmt, self.data, self.pos)
+    self.pos += size
+    return values
+
+  def bytes(self, size):
+    value = self.data[self.pos:self.pos + size]
+    if len(value) != size:
+      raise RuntimeError('trace truncated at offset {}'.format(self.pos))
+    self.pos += size
+    return value
+
+def decode(data):
+  start = data.find(b'ZTRC')
+  if start < 0:
+    raise RuntimeError('no trace header found')
+  r = Reader(data[start + 4:])
+
+  version, tick_ms, reload, lost = r.read('<BBII')
+  if version != 1:
+    raise RuntimeError('unsupported trace version {}'.format(version))
+
+  (task_count,) = r.read('<B')
+  tasks = []
+  for _ in range(task_count):
+    (length,) = r.read('<B')
+    tasks.append(r.bytes(length).decode('utf-8', 'replace') or 'idle')
+
+  (event_count,) = r.read('<H')
+  events = [r.read('<IIBBHI') for _ in range(event_count)]
+  return tick_ms, reload, lost, tasks, events
+
+def task_label(tasks, index):
+  if index < len(tasks):
+    return tasks[index]
+  return 'task {}'.format(index)
+
+def describe(kind, data, tasks):
+  if kind == 0:
+    return 'switch from {}'.format(task_label(tasks, data))
+  if kind in (1, 2):
+    return '{} 0x{:08x}'.format(KINDS[kind], data)
+  if kind in (3, 4):
+    name = EXCEPTIONS.get(data, 'IRQ {}'.format(data - 16))
+    return '{} {}'.format(KINDS[kind], name)
+  return 'unknown event {} 0x{:08x}'.format(kind, data)
+
+def main(args):
+  if len(args) != 2:
+    sys.stderr.write(__doc__)
+    return 1
+  with open(args[1], 'rb') as f:
+    tick_ms, reload, lost, tasks, events = decode(f.read())
+
+  if lost:
+    print('{} older events were lost'.format(lost))
+  if not events:
+    return 0
+
+  def micros(tick, elapsed):
+    return (tick + float(elapsed) / (reload + 1)) * tick_ms * 1000
+
+  first = micros(events[0][0], events[0][1])
+  for tick, elapsed, kind, task, _, data in events:
+    t = micros(tick, elapsed) - first
+    print('{:>12.1f} us  {:<16} {}'.format(t, task_label(tasks, task),
+        describe(kind, data, tasks)))
+  return 0
+
+if __name__ == '__main__':
+  sys.exit(main(sys.argv))